@@ -0,0 +1,33 @@
+//! Decode arbitrary bytes as a `Package<Centralex>`.
+//!
+//! Decoding must not panic, and a decoded package must survive a round trip:
+//! encoding may drop unused body bytes or shorten overlong strings, but after
+//! that the encoding must be stable.
+
+#![no_main]
+
+use itelex::centralex::Centralex;
+use itelex::Package;
+use libfuzzer_sys::fuzz_target;
+
+fn encode(package: &Package<Centralex>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    package
+        .serialize(&mut bytes)
+        .expect("decoded packages can be encoded");
+    bytes
+}
+
+fuzz_target!(|data: &[u8]| {
+    let package = match Package::<Centralex>::deserialize(&mut &data[..]) {
+        Ok(package) => package,
+        Err(_) => return,
+    };
+
+    let encoded = encode(&package);
+    let decoded =
+        Package::<Centralex>::deserialize(&mut &encoded[..]).expect("encoded packages can be decoded");
+
+    assert!(decoded.package_type() == package.package_type());
+    assert_eq!(encode(&decoded), encoded);
+});
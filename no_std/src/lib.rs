@@ -0,0 +1,94 @@
+//! Builds the client and centralex packages without `std`, as on the
+//! microcontrollers of teleprinter adapters:
+//!
+//! ```sh
+//! cargo build --manifest-path no_std/Cargo.toml
+//! cargo test --manifest-path no_std/Cargo.toml
+//! ```
+//!
+//! As this crate is `no_std`, the build fails as soon as `itelex` uses `std`
+//! with only the `client` and `centralex` features enabled.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use itelex::centralex::{self, Centralex};
+use itelex::io::{self, Read, Write};
+use itelex::{Package, PackageBody};
+
+/// A serial line to a centralex server, receiving from a fixed buffer.
+pub struct Serial<'a> {
+    pub received: &'a [u8],
+    pub sent: Vec<u8>,
+}
+
+impl Read for Serial<'_> {
+    fn read_exact(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        self.received.read_exact(buffer)
+    }
+}
+
+impl Write for Serial<'_> {
+    fn write_all(&mut self, buffer: &[u8]) -> io::Result<()> {
+        self.sent.write_all(buffer)
+    }
+}
+
+/// Handle one package from the centralex server, accepting calls and
+/// answering heartbeats.
+pub fn respond(serial: &mut Serial) -> io::Result<Package<Centralex>> {
+    let package = Package::<Centralex>::deserialize(serial)?;
+
+    if package.is::<centralex::RemCall>() {
+        centralex::RemAck {}.serialize(serial)?;
+    } else if package.is::<centralex::Heartbeat>() {
+        centralex::Heartbeat {}.serialize(serial)?;
+    }
+
+    Ok(package)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use core::net::Ipv4Addr;
+    use itelex::client::{self, Client};
+    use itelex::Extension;
+
+    #[test]
+    fn accepts_calls() {
+        let call = centralex::RemCall::new(Ipv4Addr::new(192, 0, 2, 1).into());
+        let mut received = Vec::new();
+        call.serialize(&mut received).unwrap();
+
+        let mut serial = Serial {
+            received: &received,
+            sent: Vec::new(),
+        };
+        let package = respond(&mut serial).unwrap();
+        assert_eq!(package, call.to_package());
+        assert_eq!(serial.sent, vec![0x84, 0]);
+
+        // nothing left to read
+        assert!(respond(&mut serial).is_err());
+    }
+
+    #[test]
+    fn client_round_trip() {
+        let package = client::DirectDial {
+            extension: Extension::from_wire(7),
+        }
+        .to_package();
+
+        let mut bytes = Vec::new();
+        package.serialize(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![0x01, 1, 7]);
+        assert_eq!(
+            Package::<Client>::deserialize(&mut &bytes[..]).unwrap(),
+            package
+        );
+    }
+}
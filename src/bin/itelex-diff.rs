@@ -0,0 +1,106 @@
+//! Print the differences between two directories.
+//!
+//! Each side is either a JSON or CSV export (as written by
+//! `itelex::server::import_export`) or the `host:port` of a directory server,
+//! which is asked for all of its entries with a `FullQuery`.
+//!
+//! Exits with 0 if the directories are the same, 1 if they differ and 2 on errors.
+
+use itelex::server::diff::diff;
+use itelex::server::import_export::{import_csv, import_json};
+use itelex::server::{DirectoryClient, PeerReply};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::path::Path;
+use std::process::exit;
+
+const USAGE: &str = "usage: itelex-diff [--server-pin PIN] LEFT RIGHT
+
+LEFT and RIGHT are JSON or CSV files, or host:port of directory servers.";
+
+struct Args {
+    server_pin: u32,
+    left: String,
+    right: String,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut server_pin = None;
+    let mut sources = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "--server-pin" => {
+                let pin = args.next().ok_or("--server-pin needs a value")?;
+                server_pin = Some(
+                    pin.parse()
+                        .map_err(|_| format!("invalid server pin {:?}", pin))?,
+                );
+            }
+            _ => sources.push(arg),
+        }
+    }
+
+    match <[String; 2]>::try_from(sources) {
+        Ok([left, right]) => Ok(Args {
+            server_pin: server_pin.unwrap_or(0),
+            left,
+            right,
+        }),
+        Err(_) => Err(String::from("expected exactly two directories")),
+    }
+}
+
+fn load(source: &str, server_pin: u32) -> std::io::Result<Vec<PeerReply>> {
+    let path = Path::new(source);
+    if !path.exists() && is_server_address(source) {
+        return DirectoryClient::connect(source)?.full_query(server_pin);
+    }
+
+    let file = File::open(path)?;
+    let import = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => import_csv(file)?,
+        _ => import_json(file)?,
+    };
+
+    for err in &import.errors {
+        eprintln!("{}: {}", source, err);
+    }
+
+    Ok(import.entries)
+}
+
+/// Whether `source` has the form `host:port`, so that a mistyped file name is
+/// not looked up as a host.
+fn is_server_address(source: &str) -> bool {
+    match source.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        exit(2);
+    });
+
+    let load = |source: &str| {
+        load(source, args.server_pin).unwrap_or_else(|err| {
+            eprintln!("{}: {}", source, err);
+            exit(2);
+        })
+    };
+    let left = load(&args.left);
+    let right = load(&args.right);
+
+    let diff = diff(&left, &right);
+    print!("{}", diff);
+
+    exit(if diff.is_empty() { 0 } else { 1 });
+}
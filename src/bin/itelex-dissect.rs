@@ -0,0 +1,134 @@
+//! Decode the packages in a captured byte stream.
+//!
+//! The input is read from a file or stdin and is taken to be hex text if it
+//! only consists of hex digits and separators, a packet capture if it starts
+//! like a pcap or pcapng file, and raw bytes otherwise.
+
+use itelex::dissect::{dissect, dissect_capture, parse_hex, pcap, PackageClass};
+use std::io::Read;
+use std::process::exit;
+
+const USAGE: &str = "usage: itelex-dissect [OPTIONS] [FILE]
+
+options:
+    -c, --class CLASS    decode as server, centralex or client packages
+                         (default: detect for each package)
+    --hex                the input is hex text
+    --binary             the input is raw bytes
+    --pcap               the input is a pcap or pcapng file, whose TCP
+                         sessions are decoded by the protocol they speak";
+
+#[derive(Copy, Clone)]
+enum Format {
+    Hex,
+    Binary,
+    Capture,
+}
+
+struct Args {
+    class: Option<PackageClass>,
+    format: Option<Format>,
+    file: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        class: None,
+        format: None,
+        file: None,
+    };
+
+    let mut arguments = std::env::args().skip(1);
+    while let Some(arg) = arguments.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "-c" | "--class" => {
+                args.class = Some(arguments.next().ok_or("--class needs a value")?.parse()?)
+            }
+            "--hex" => args.format = Some(Format::Hex),
+            "--binary" => args.format = Some(Format::Binary),
+            "--pcap" => args.format = Some(Format::Capture),
+            _ if args.file.is_none() => args.file = Some(arg),
+            _ => return Err(format!("unexpected argument {:?}", arg)),
+        }
+    }
+
+    if args.class.is_some() && matches!(args.format, Some(Format::Capture)) {
+        return Err(String::from("--class cannot be used with --pcap"));
+    }
+
+    Ok(args)
+}
+
+fn looks_like_hex(input: &[u8]) -> bool {
+    input
+        .iter()
+        .all(|byte| byte.is_ascii_hexdigit() || byte.is_ascii_whitespace() || b",:x".contains(byte))
+}
+
+fn print_sessions(input: &[u8]) {
+    let sessions = dissect_capture(input).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        exit(2);
+    });
+
+    for (index, session) in sessions.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!("{}", session);
+    }
+
+    let failed = sessions
+        .iter()
+        .flat_map(|session| &session.events)
+        .any(|event| event.is_error());
+    if failed {
+        exit(1);
+    }
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        exit(2);
+    });
+
+    let mut input = Vec::new();
+    let result = match &args.file {
+        Some(file) => std::fs::File::open(file).and_then(|mut file| file.read_to_end(&mut input)),
+        None => std::io::stdin().read_to_end(&mut input),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(2);
+    }
+
+    let format = args.format.unwrap_or(if pcap::is_capture(&input) {
+        Format::Capture
+    } else if looks_like_hex(&input) {
+        Format::Hex
+    } else {
+        Format::Binary
+    });
+    let bytes = match format {
+        Format::Capture => return print_sessions(&input),
+        Format::Hex => parse_hex(&String::from_utf8_lossy(&input)).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(2);
+        }),
+        Format::Binary => input,
+    };
+
+    let frames = dissect(&bytes, args.class);
+    for frame in &frames {
+        println!("{}", frame);
+    }
+
+    if frames.iter().any(|frame| frame.decoded.is_err()) {
+        exit(1);
+    }
+}
@@ -0,0 +1,99 @@
+//! Forward connections to a directory server, centralex server or subscriber,
+//! logging every package sent either way.
+
+use itelex::dissect::SessionKind;
+use itelex::proxy::{Proxy, Record};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::process::exit;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "usage: itelex-proxy [OPTIONS] UPSTREAM
+
+Forward connections to UPSTREAM (host:port) and log what is sent either way.
+
+options:
+    -l, --listen ADDRESS    the address to listen on
+                            (default: 127.0.0.1 and the port of UPSTREAM)
+    -k, --kind KIND         decode as directory, text, centralex or client
+                            (default: detect for each connection)";
+
+struct Args {
+    listen: Option<String>,
+    kind: Option<SessionKind>,
+    upstream: String,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut listen = None;
+    let mut kind = None;
+    let mut upstream = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "-l" | "--listen" => listen = Some(args.next().ok_or("--listen needs a value")?),
+            "-k" | "--kind" => kind = Some(args.next().ok_or("--kind needs a value")?.parse()?),
+            _ if upstream.is_none() => upstream = Some(arg),
+            _ => return Err(format!("unexpected argument {:?}", arg)),
+        }
+    }
+
+    Ok(Args {
+        listen,
+        kind,
+        upstream: upstream.ok_or("missing upstream")?,
+    })
+}
+
+fn log(client: SocketAddr, record: Record) {
+    match record {
+        // events carry the time they happened at
+        Record::Event(event) => println!("{}  {}", client, event),
+        record => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            println!(
+                "{}  {}.{:06}  {}",
+                client,
+                now.as_secs(),
+                now.subsec_micros(),
+                record
+            );
+        }
+    }
+}
+
+fn run(args: Args) -> std::io::Result<()> {
+    let upstream: Vec<SocketAddr> = args.upstream.to_socket_addrs()?.collect();
+
+    let listener = match &args.listen {
+        Some(address) => TcpListener::bind(address.as_str())?,
+        None => TcpListener::bind(("127.0.0.1", upstream.first().map_or(0, SocketAddr::port)))?,
+    };
+    eprintln!("forwarding {} to {}", listener.local_addr()?, args.upstream);
+
+    let mut proxy = Proxy::new(&upstream[..], log)?;
+    if let Some(kind) = args.kind {
+        proxy = proxy.with_kind(kind);
+    }
+
+    Arc::new(proxy).listen(listener)
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        exit(2);
+    });
+
+    if let Err(err) = run(args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
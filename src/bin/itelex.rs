@@ -0,0 +1,183 @@
+//! Make requests to a directory server from the command line.
+
+use itelex::server::import_export::export_json;
+use itelex::server::{DirectoryClient, PeerReply};
+use std::process::exit;
+
+const USAGE: &str = "usage: itelex [OPTIONS] COMMAND
+
+commands:
+    query NUMBER              look up the entry for NUMBER
+    search WORD...            find all entries whose name contains every WORD
+    list                      list all entries, needs the server pin
+    update NUMBER PIN PORT    point the entry for NUMBER at this host and PORT
+
+options:
+    -s, --server ADDRESS      host:port of the directory server
+                              (default: $ITELEX_SERVER)
+    --server-pin PIN          the server pin for list (default: $ITELEX_SERVER_PIN)
+    --json                    print entries as JSON instead of a table";
+
+enum Command {
+    Query(u32),
+    Search(String),
+    List,
+    Update { number: u32, pin: u16, port: u16 },
+}
+
+struct Args {
+    server: String,
+    server_pin: u32,
+    json: bool,
+    command: Command,
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing {}", name))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} {:?}", name, value))
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut server = std::env::var("ITELEX_SERVER").ok();
+    let mut server_pin = std::env::var("ITELEX_SERVER_PIN").ok();
+    let mut json = false;
+    let mut positional = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "-s" | "--server" => server = Some(args.next().ok_or("--server needs a value")?),
+            "--server-pin" => server_pin = Some(args.next().ok_or("--server-pin needs a value")?),
+            "--json" => json = true,
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next().as_deref() {
+        Some("query") => Command::Query(parse("number", positional.next())?),
+        Some("search") => {
+            let words: Vec<String> = positional.by_ref().collect();
+            if words.is_empty() {
+                return Err(String::from("missing pattern"));
+            }
+            Command::Search(words.join(" "))
+        }
+        Some("list") => Command::List,
+        Some("update") => Command::Update {
+            number: parse("number", positional.next())?,
+            pin: parse("pin", positional.next())?,
+            port: parse("port", positional.next())?,
+        },
+        Some(command) => return Err(format!("unknown command {:?}", command)),
+        None => return Err(String::from("missing command")),
+    };
+    if let Some(arg) = positional.next() {
+        return Err(format!("unexpected argument {:?}", arg));
+    }
+
+    let server_pin = match server_pin {
+        Some(server_pin) => parse("server pin", Some(server_pin))?,
+        None if matches!(command, Command::List) => {
+            return Err(String::from("list needs a server pin"))
+        }
+        None => 0,
+    };
+
+    Ok(Args {
+        server: server.ok_or("no directory server given")?,
+        server_pin,
+        json,
+        command,
+    })
+}
+
+fn print_table(entries: &[PeerReply]) {
+    let rows: Vec<[String; 6]> = entries
+        .iter()
+        .map(|entry| {
+            [
+                entry.number.to_string(),
+                entry.name.to_string(),
+                entry.client_type.name().to_string(),
+                entry.address().to_string(),
+                entry.extension.to_string(),
+                if entry.disabled() {
+                    String::from("disabled")
+                } else {
+                    entry.timestamp.to_string()
+                },
+            ]
+        })
+        .collect();
+
+    let header = ["number", "name", "type", "address", "ext", "updated"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: &[&str]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+
+    print_row(&header);
+    for row in &rows {
+        print_row(&row.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+}
+
+fn run(args: Args) -> std::io::Result<()> {
+    let mut client = DirectoryClient::connect(args.server.as_str())?;
+
+    let entries = match args.command {
+        Command::Query(number) => match client.query(number)? {
+            Some(entry) => vec![entry],
+            None => {
+                eprintln!("{} not found", number);
+                exit(1);
+            }
+        },
+        Command::Search(pattern) => client.search(&pattern)?,
+        Command::List => client.full_query(args.server_pin)?,
+        Command::Update { number, pin, port } => {
+            let ipaddress = client.update(number, pin, port)?;
+            println!("{}:{}", ipaddress, port);
+            return Ok(());
+        }
+    };
+
+    if args.json {
+        export_json(&entries, std::io::stdout())?;
+        println!();
+    } else {
+        print_table(&entries);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        exit(2);
+    });
+
+    if let Err(err) = run(args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
@@ -0,0 +1,267 @@
+use super::*;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// A subscriber registered at a centralex server, waiting for calls.
+#[derive(Debug)]
+pub struct CentralexClient {
+    stream: TcpStream,
+    heartbeat_interval: Duration,
+    timeout: Duration,
+}
+
+/// A call the centralex server forwarded to us.
+///
+/// After the `RemAck`, `stream` carries the client protocol of the call.
+#[derive(Debug)]
+pub struct IncomingCall {
+    pub caller: RemCall,
+    pub stream: TcpStream,
+}
+
+impl CentralexClient {
+    /// Connect to the centralex server at `address` and register as `number`.
+    pub fn connect(address: impl ToSocketAddrs, number: u32, pin: u16) -> std::io::Result<Self> {
+        Self::register(TcpStream::connect(address)?, number, pin)
+    }
+
+    /// Register as `number` over an established connection to a centralex server.
+    ///
+    /// If the server rejects the registration the error has the kind
+    /// `PermissionDenied` and wraps the `Reject`.
+    pub fn register(stream: TcpStream, number: u32, pin: u16) -> std::io::Result<Self> {
+        let mut client = CentralexClient {
+            stream,
+            heartbeat_interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(60),
+        };
+
+        client.stream.set_read_timeout(Some(client.timeout))?;
+        client.send(RemConnect { number, pin })?;
+
+        let reply = client.receive()?;
+        if reply.is::<RemConfirm>() {
+            Ok(client)
+        } else if let Some(reject) = reply.downcast::<Reject>() {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                *reject,
+            ))
+        } else {
+            Err(unexpected_package())
+        }
+    }
+
+    /// Send a `Heartbeat` every `interval` and give up on the connection if the
+    /// server stays silent for `timeout`.
+    pub fn with_heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self.timeout = timeout;
+        self
+    }
+
+    /// The connection to the centralex server.
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Keep the connection alive until the server forwards a call, then
+    /// acknowledge and return it.
+    ///
+    /// Fails with `TimedOut` if the server stops sending heartbeats.
+    pub fn wait_for_call(mut self) -> std::io::Result<IncomingCall> {
+        let mut last_received = Instant::now();
+        let mut next_heartbeat = Instant::now();
+
+        loop {
+            let now = Instant::now();
+            if now.duration_since(last_received) >= self.timeout {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "centralex server stopped sending heartbeats",
+                ));
+            }
+
+            if now >= next_heartbeat {
+                self.send(Heartbeat {})?;
+                next_heartbeat = now + self.heartbeat_interval;
+            }
+
+            let wait = std::cmp::min(next_heartbeat, last_received + self.timeout) - now;
+            if !self.poll(wait)? {
+                continue;
+            }
+
+            let package = self.receive()?;
+            last_received = Instant::now();
+
+            match package.package_type() {
+                Centralex::Heartbeat => {}
+                Centralex::RemCall => {
+                    self.send(RemAck {})?;
+                    self.stream.set_read_timeout(None)?;
+
+                    return Ok(IncomingCall {
+                        caller: *package.downcast::<RemCall>().unwrap(),
+                        stream: self.stream,
+                    });
+                }
+                Centralex::End => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        "centralex server ended the connection",
+                    ))
+                }
+                Centralex::Reject => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        *package.downcast::<Reject>().unwrap(),
+                    ))
+                }
+                _ => return Err(unexpected_package()),
+            }
+        }
+    }
+
+    /// Unregister by sending an `End` and closing the connection.
+    pub fn end(mut self) -> std::io::Result<()> {
+        self.send(End {})
+    }
+
+    /// Wait up to `timeout` for input, without consuming any.
+    fn poll(&mut self, timeout: Duration) -> std::io::Result<bool> {
+        if timeout == Duration::from_secs(0) {
+            return Ok(false);
+        }
+
+        self.stream.set_read_timeout(Some(timeout))?;
+        let result = self.stream.peek(&mut [0]);
+        self.stream.set_read_timeout(Some(self.timeout))?;
+
+        match result {
+            Ok(0) => Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(true),
+            Err(err)
+                if err.kind() == std::io::ErrorKind::WouldBlock
+                    || err.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn send(&mut self, package: impl Into<Package<Centralex>>) -> std::io::Result<()> {
+        let mut buffer = Vec::new();
+        package.into().serialize(&mut buffer)?;
+
+        self.stream.write_all(&buffer)?;
+        self.stream.flush()
+    }
+
+    fn receive(&mut self) -> std::io::Result<Package<Centralex>> {
+        Package::<Centralex>::deserialize(&mut self.stream)
+    }
+}
+
+fn unexpected_package() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "unexpected package from centralex server",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpListener};
+
+    fn send(stream: &mut TcpStream, package: impl Into<Package<Centralex>>) {
+        package.into().serialize(stream).unwrap();
+    }
+
+    fn receive(stream: &mut TcpStream) -> Package<Centralex> {
+        Package::<Centralex>::deserialize(stream).unwrap()
+    }
+
+    /// Run `server` on the connection of a client registering with `pin`.
+    fn with_server(
+        pin: u16,
+        server: impl FnOnce(TcpStream) + Send + 'static,
+    ) -> std::io::Result<CentralexClient> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || server(listener.accept().unwrap().0));
+
+        CentralexClient::connect(address, 1234, pin)
+    }
+
+    #[test]
+    fn rejected() {
+        let err = with_server(1, |mut stream| {
+            let connect = receive(&mut stream);
+            assert_eq!(
+                connect.downcast_ref::<RemConnect>(),
+                Some(&RemConnect {
+                    number: 1234,
+                    pin: 1
+                })
+            );
+            send(&mut stream, Reject::from(String::from("wrong pin")));
+        })
+        .unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(err.to_string(), "wrong pin");
+    }
+
+    #[test]
+    fn incoming_call() {
+        let client = with_server(4711, |mut stream| {
+            assert!(receive(&mut stream).is::<RemConnect>());
+            send(&mut stream, RemConfirm {});
+
+            for _ in 0..2 {
+                assert!(receive(&mut stream).is::<Heartbeat>());
+                send(&mut stream, Heartbeat {});
+            }
+
+            send(
+                &mut stream,
+                RemCall {
+                    remote_ip_v4: Ipv4Addr::new(192, 0, 2, 1),
+                    remote_ip_v6: Ipv6Addr::UNSPECIFIED,
+                },
+            );
+            stream.write_all(b"call").unwrap();
+
+            assert!(receive(&mut stream).is::<RemAck>());
+        })
+        .unwrap()
+        .with_heartbeat(Duration::from_millis(20), Duration::from_secs(5));
+
+        let mut call = client.wait_for_call().unwrap();
+        assert_eq!(call.caller.remote_ip_v4, Ipv4Addr::new(192, 0, 2, 1));
+
+        let mut data = [0; 4];
+        call.stream.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"call");
+    }
+
+    #[test]
+    fn missing_heartbeats() {
+        let client = with_server(4711, |mut stream| {
+            assert!(receive(&mut stream).is::<RemConnect>());
+            send(&mut stream, RemConfirm {});
+
+            std::thread::sleep(Duration::from_secs(1));
+        })
+        .unwrap()
+        .with_heartbeat(Duration::from_millis(20), Duration::from_millis(100));
+
+        let err = client.wait_for_call().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,229 @@
+//! Authentication of centralex subscribers against the pins of a directory.
+
+use super::*;
+use crate::server::{
+    ClientType, ClientUpdate, DirectoryClient, DirectoryServer, DirectoryStore, Error, Refusal,
+};
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+
+/// Accepts subscribers whose pin matches their entry in a local `DirectoryServer`.
+pub struct StoreAuthenticator<S> {
+    directory: Arc<DirectoryServer<S>>,
+    relay_address: Option<Ipv4Addr>,
+}
+
+impl<S: DirectoryStore + Send> StoreAuthenticator<S> {
+    pub fn new(directory: Arc<DirectoryServer<S>>) -> Self {
+        StoreAuthenticator {
+            directory,
+            relay_address: None,
+        }
+    }
+
+    /// Point the entries of accepted subscribers at `relay_address` and their
+    /// port on the relay, like a `ClientUpdate` would.
+    pub fn with_update(mut self, relay_address: Ipv4Addr) -> Self {
+        self.relay_address = Some(relay_address);
+        self
+    }
+
+    pub fn directory(&self) -> &Arc<DirectoryServer<S>> {
+        &self.directory
+    }
+}
+
+impl<S: DirectoryStore + Send> Authenticator for StoreAuthenticator<S> {
+    fn authenticate(&self, connect: &RemConnect, source: SocketAddr) -> Result<(), Reject> {
+        let entry = self
+            .directory
+            .verify_pin(source.ip(), connect.number, connect.pin)
+            .map_err(reject)?;
+        if entry.disabled() || entry.client_type == ClientType::Deleted {
+            return Err(RejectReason::Disabled.into());
+        }
+
+        Ok(())
+    }
+
+    fn register(&self, connect: &RemConnect, registration: &Registration) -> Result<(), Reject> {
+        if let Some(relay_address) = self.relay_address {
+            let update = ClientUpdate {
+                number: connect.number,
+                pin: connect.pin,
+                port: registration.port,
+            };
+            self.directory
+                .update(registration.source.ip(), relay_address, &update)
+                .map_err(reject)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Accepts subscribers by sending a `ClientUpdate` with their pin and port on
+/// the relay to a remote directory server.
+///
+/// The directory sees the update coming from the relay, so it only points the
+/// entries at the relay if the relay makes its requests from its public address.
+///
+/// The directory can only check a pin by updating the entry, so subscribers
+/// are authenticated with an update to port 0, which is followed by the real
+/// port once it is bound.
+pub struct RemoteAuthenticator {
+    directory: Vec<SocketAddr>,
+}
+
+impl RemoteAuthenticator {
+    pub fn new(directory: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Ok(RemoteAuthenticator {
+            directory: directory.to_socket_addrs()?.collect(),
+        })
+    }
+
+    /// Update the entry of the subscriber sending `connect` to `port`.
+    fn update(&self, connect: &RemConnect, port: u16) -> Result<(), Reject> {
+        let result = DirectoryClient::connect(&self.directory[..])
+            .and_then(|mut client| client.update(connect.number, connect.pin, port));
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => match err.get_ref().and_then(|err| err.downcast_ref::<Error>()) {
+                Some(err) => Err(match Refusal::from_message(&err.message) {
+                    Some(refusal) => reject(refusal),
+                    None => RejectReason::Other(err.message.clone()).into(),
+                }),
+                None => Err(RejectReason::Unavailable.into()),
+            },
+        }
+    }
+}
+
+impl Authenticator for RemoteAuthenticator {
+    fn authenticate(&self, connect: &RemConnect, _: SocketAddr) -> Result<(), Reject> {
+        self.update(connect, 0)
+    }
+
+    fn register(&self, connect: &RemConnect, registration: &Registration) -> Result<(), Reject> {
+        self.update(connect, registration.port)
+    }
+}
+
+/// The `Reject` for a `Refusal` of the directory.
+fn reject(refusal: Refusal) -> Reject {
+    let reason = match refusal {
+        Refusal::UnknownNumber => RejectReason::UnknownNumber,
+        Refusal::WrongPin => RejectReason::WrongPin,
+        Refusal::NotUpdatable(_) => RejectReason::NotUpdatable,
+        Refusal::RateLimited => RejectReason::TooManyFailures,
+        Refusal::Internal(_) => RejectReason::Unavailable,
+    };
+
+    reason.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{DirectoryTimestamp, MemoryStore, PeerFlags, PeerReply};
+    use crate::Extension;
+    use std::net::TcpListener;
+
+    fn directory() -> Arc<DirectoryServer<MemoryStore>> {
+        let mut store = MemoryStore::new();
+        for (number, flags, client_type) in &[
+            (1234, PeerFlags::empty(), ClientType::BaudotDynIp),
+            (5678, PeerFlags::DISABLED, ClientType::BaudotDynIp),
+            (9012, PeerFlags::empty(), ClientType::BaudotIpaddress),
+        ] {
+            store
+                .upsert(PeerReply {
+                    number: *number,
+                    name: "Subscriber".into(),
+                    flags: *flags,
+                    client_type: *client_type,
+                    hostname: "".into(),
+                    ipaddress: Ipv4Addr::new(192, 0, 2, 1),
+                    port: 134,
+                    extension: Extension::NONE,
+                    pin: 4711,
+                    timestamp: DirectoryTimestamp::NEVER,
+                })
+                .unwrap();
+        }
+
+        Arc::new(DirectoryServer::new(store, 0))
+    }
+
+    fn start_relay(authenticator: impl Authenticator + 'static) -> SocketAddr {
+        let server = Arc::new(
+            CentralexServer::new(authenticator).with_public_address(Ipv4Addr::LOCALHOST.into()),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || server.listen(listener));
+
+        address
+    }
+
+    fn reason(err: std::io::Error) -> RejectReason {
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        err.into_inner()
+            .unwrap()
+            .downcast::<Reject>()
+            .unwrap()
+            .reason()
+    }
+
+    #[test]
+    fn store() {
+        let directory = directory();
+        let relay = start_relay(
+            StoreAuthenticator::new(directory.clone()).with_update(Ipv4Addr::new(198, 51, 100, 1)),
+        );
+
+        let err = CentralexClient::connect(relay, 1234, 1).unwrap_err();
+        assert_eq!(reason(err), RejectReason::WrongPin);
+
+        let err = CentralexClient::connect(relay, 1, 4711).unwrap_err();
+        assert_eq!(reason(err), RejectReason::UnknownNumber);
+
+        let err = CentralexClient::connect(relay, 5678, 4711).unwrap_err();
+        assert_eq!(reason(err), RejectReason::Disabled);
+
+        let err = CentralexClient::connect(relay, 9012, 4711).unwrap_err();
+        assert_eq!(reason(err), RejectReason::NotUpdatable);
+
+        let _client = CentralexClient::connect(relay, 1234, 4711).unwrap();
+        let entry = directory.store().get(1234).unwrap().unwrap();
+        assert_eq!(entry.ipaddress, Ipv4Addr::new(198, 51, 100, 1));
+        assert_ne!(entry.port, 134);
+    }
+
+    #[test]
+    fn remote() {
+        let directory = directory();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn({
+            let directory = directory.clone();
+            move || crate::server::listen(listener, directory)
+        });
+
+        let relay = start_relay(RemoteAuthenticator::new(address).unwrap());
+
+        let err = CentralexClient::connect(relay, 1234, 1).unwrap_err();
+        assert_eq!(reason(err), RejectReason::WrongPin);
+
+        let err = CentralexClient::connect(relay, 9012, 4711).unwrap_err();
+        assert_eq!(reason(err), RejectReason::NotUpdatable);
+
+        let _client = CentralexClient::connect(relay, 1234, 4711).unwrap();
+        let entry = directory.store().get(1234).unwrap().unwrap();
+        assert_eq!(entry.ipaddress, Ipv4Addr::LOCALHOST);
+        assert_ne!(entry.port, 134);
+        assert_ne!(entry.port, 0);
+    }
+}
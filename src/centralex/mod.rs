@@ -18,3 +18,23 @@ fn construct_package() {
 
     package.downcast_mut::<RemAck>().unwrap();
 }
+
+#[cfg(feature = "std")]
+mod client;
+#[cfg(feature = "std")]
+pub use client::*;
+
+#[cfg(feature = "std")]
+mod server;
+#[cfg(feature = "std")]
+pub use server::*;
+
+#[cfg(feature = "server")]
+mod directory;
+#[cfg(feature = "server")]
+pub use directory::*;
+
+#[cfg(feature = "std")]
+mod supervisor;
+#[cfg(feature = "std")]
+pub use supervisor::*;
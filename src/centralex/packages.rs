@@ -1,4 +1,11 @@
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+use crate::io;
+use crate::wire::{Decode, Encode};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct RemConnect {
@@ -6,30 +13,83 @@ pub struct RemConnect {
     pub pin: u16,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct RemConfirm {}
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct RemCall {
-    pub remote_ip_v4: std::net::Ipv4Addr,
-    pub remote_ip_v6: std::net::Ipv6Addr,
+    pub remote_ip_v4: Ipv4Addr,
+    pub remote_ip_v6: Ipv6Addr,
+}
+
+impl RemCall {
+    /// A `RemCall` for a caller at `ip`, leaving the field for the other
+    /// address family unspecified. IPv4-mapped IPv6 addresses are sent as IPv4.
+    pub fn new(ip: IpAddr) -> Self {
+        let ip = match ip {
+            IpAddr::V6(ip) => ip
+                .to_ipv4_mapped()
+                .map(IpAddr::V4)
+                .unwrap_or(IpAddr::V6(ip)),
+            ip => ip,
+        };
+
+        match ip {
+            IpAddr::V4(ip) => RemCall {
+                remote_ip_v4: ip,
+                remote_ip_v6: Ipv6Addr::UNSPECIFIED,
+            },
+            IpAddr::V6(ip) => RemCall {
+                remote_ip_v4: Ipv4Addr::UNSPECIFIED,
+                remote_ip_v6: ip,
+            },
+        }
+    }
+
+    /// The address of the caller, or `None` if neither field is specified.
+    ///
+    /// If both are, the IPv4 address is used.
+    pub fn caller(&self) -> Option<IpAddr> {
+        if !self.remote_ip_v4.is_unspecified() {
+            Some(self.remote_ip_v4.into())
+        } else if !self.remote_ip_v6.is_unspecified() {
+            Some(match self.remote_ip_v6.to_ipv4_mapped() {
+                Some(ip) => ip.into(),
+                None => self.remote_ip_v6.into(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl From<IpAddr> for RemCall {
+    fn from(ip: IpAddr) -> Self {
+        Self::new(ip)
+    }
+}
+
+impl From<SocketAddr> for RemCall {
+    fn from(address: SocketAddr) -> Self {
+        Self::new(address.ip())
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct RemAck {}
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct End {}
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct Heartbeat {}
@@ -41,28 +101,201 @@ pub struct Reject {
     pub message: String,
 }
 
-impl<W: std::io::Write> binserde::Serialize<W> for Reject {
-    fn serialize_ne(&self, writer: &mut W) -> std::io::Result<()> {
+wire_struct! {
+    RemConnect { number, pin }
+    RemConfirm {}
+    RemCall { remote_ip_v4, remote_ip_v6 }
+    RemAck {}
+    End {}
+    Heartbeat {}
+}
+
+impl Encode for Reject {
+    fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
         writer.write_all(self.message.as_bytes())?;
         writer.write_all(&[0])?;
 
         Ok(())
     }
 }
-impl<R: std::io::Read> binserde::Deserialize<R> for Reject {
-    fn deserialize_ne(reader: &mut R) -> std::io::Result<Self> {
+impl Decode for Reject {
+    fn decode(reader: &mut impl io::Read) -> io::Result<Self> {
         let mut buffer = Vec::new();
         loop {
-            let byte = u8::deserialize_ne(reader)?;
+            let byte = u8::decode(reader)?;
 
             if byte != 0 {
                 buffer.push(byte);
             } else {
                 return Ok(Reject {
                     message: String::from_utf8(buffer)
-                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?,
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
                 });
             }
         }
     }
 }
+
+impl From<String> for Reject {
+    fn from(string: String) -> Self {
+        Reject { message: string }
+    }
+}
+
+impl fmt::Display for Reject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl core::error::Error for Reject {}
+
+impl Reject {
+    /// The reason for this `Reject`, if it is one of the known ones.
+    pub fn reason(&self) -> RejectReason {
+        self.message.parse().unwrap()
+    }
+}
+
+/// The messages of `Reject`s for the known `RejectReason`s.
+///
+/// `Reject`s only carry a message, so these are part of the protocol: servers
+/// send them and clients match them exactly. They must never change.
+pub mod reject_message {
+    pub const UNKNOWN_NUMBER: &str = "unknown number";
+    pub const WRONG_PIN: &str = "wrong pin";
+    pub const DISABLED: &str = "disabled";
+    pub const NOT_UPDATABLE: &str = "not updatable";
+    pub const TOO_MANY_FAILURES: &str = "too many failed attempts";
+    pub const UNAVAILABLE: &str = "unavailable";
+}
+
+/// Why a centralex server refused a registration.
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum RejectReason {
+    UnknownNumber,
+    WrongPin,
+    /// the directory entry of the number is disabled
+    Disabled,
+    /// the directory entry of the number does not take updates, as its address is fixed
+    NotUpdatable,
+    /// the subscriber failed to authenticate too often and has to wait
+    TooManyFailures,
+    /// the registration could not be checked right now
+    Unavailable,
+    Other(String),
+}
+
+impl RejectReason {
+    /// Whether retrying the same registration is pointless.
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            RejectReason::UnknownNumber
+                | RejectReason::WrongPin
+                | RejectReason::Disabled
+                | RejectReason::NotUpdatable
+        )
+    }
+
+    /// The message of a `Reject` for this reason, see `reject_message`.
+    pub fn as_str(&self) -> &str {
+        match self {
+            RejectReason::UnknownNumber => reject_message::UNKNOWN_NUMBER,
+            RejectReason::WrongPin => reject_message::WRONG_PIN,
+            RejectReason::Disabled => reject_message::DISABLED,
+            RejectReason::NotUpdatable => reject_message::NOT_UPDATABLE,
+            RejectReason::TooManyFailures => reject_message::TOO_MANY_FAILURES,
+            RejectReason::Unavailable => reject_message::UNAVAILABLE,
+            RejectReason::Other(message) => message,
+        }
+    }
+}
+
+impl core::str::FromStr for RejectReason {
+    type Err = core::convert::Infallible;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        Ok(match string {
+            reject_message::UNKNOWN_NUMBER => RejectReason::UnknownNumber,
+            reject_message::WRONG_PIN => RejectReason::WrongPin,
+            reject_message::DISABLED => RejectReason::Disabled,
+            reject_message::NOT_UPDATABLE => RejectReason::NotUpdatable,
+            reject_message::TOO_MANY_FAILURES => RejectReason::TooManyFailures,
+            reject_message::UNAVAILABLE => RejectReason::Unavailable,
+            other => RejectReason::Other(other.into()),
+        })
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<RejectReason> for Reject {
+    fn from(reason: RejectReason) -> Self {
+        Reject {
+            message: reason.as_str().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rem_call_caller() {
+        let v4 = Ipv4Addr::new(192, 0, 2, 1);
+        let v6 = "2001:db8::1".parse::<Ipv6Addr>().unwrap();
+
+        let call = RemCall::new(v4.into());
+        assert_eq!(call.remote_ip_v6, Ipv6Addr::UNSPECIFIED);
+        assert_eq!(call.caller(), Some(v4.into()));
+
+        let call = RemCall::from(SocketAddr::new(v6.into(), 134));
+        assert_eq!(call.remote_ip_v4, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(call.caller(), Some(v6.into()));
+
+        let call = RemCall::new(v4.to_ipv6_mapped().into());
+        assert_eq!(call.remote_ip_v4, v4);
+        assert_eq!(call.remote_ip_v6, Ipv6Addr::UNSPECIFIED);
+
+        let call = RemCall {
+            remote_ip_v4: Ipv4Addr::UNSPECIFIED,
+            remote_ip_v6: v4.to_ipv6_mapped(),
+        };
+        assert_eq!(call.caller(), Some(v4.into()));
+
+        let call = RemCall {
+            remote_ip_v4: Ipv4Addr::UNSPECIFIED,
+            remote_ip_v6: Ipv6Addr::UNSPECIFIED,
+        };
+        assert_eq!(call.caller(), None);
+    }
+
+    #[test]
+    fn reject_reason() {
+        for (reason, message) in &[
+            (RejectReason::UnknownNumber, reject_message::UNKNOWN_NUMBER),
+            (RejectReason::WrongPin, reject_message::WRONG_PIN),
+            (RejectReason::Disabled, reject_message::DISABLED),
+            (RejectReason::NotUpdatable, reject_message::NOT_UPDATABLE),
+            (
+                RejectReason::TooManyFailures,
+                reject_message::TOO_MANY_FAILURES,
+            ),
+            (RejectReason::Unavailable, reject_message::UNAVAILABLE),
+            (RejectReason::Other(String::from("occ")), "occ"),
+        ] {
+            let reject = Reject::from(reason.clone());
+            assert_eq!(reject.message, *message);
+            assert_eq!(&reject.reason(), reason);
+        }
+
+        assert!(RejectReason::WrongPin.is_permanent());
+        assert!(!RejectReason::Unavailable.is_permanent());
+    }
+}
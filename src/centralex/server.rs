@@ -0,0 +1,576 @@
+use super::*;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How often a subscriber's link and public port are checked for activity, on
+/// platforms where the server can not wait for both at once.
+#[cfg(not(unix))]
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Decides which subscribers may register at a `CentralexServer`.
+pub trait Authenticator: Send + Sync {
+    /// Check `connect` sent from `source`, before a public port is bound for it.
+    fn authenticate(&self, connect: &RemConnect, source: SocketAddr) -> Result<(), Reject>;
+
+    /// Called once the public port of an authenticated subscriber is bound,
+    /// e.g. to point its directory entry at the port. Returning an error
+    /// rejects the subscriber after all.
+    fn register(&self, _connect: &RemConnect, _registration: &Registration) -> Result<(), Reject> {
+        Ok(())
+    }
+}
+
+impl<F: Fn(&RemConnect) -> Result<(), Reject> + Send + Sync> Authenticator for F {
+    fn authenticate(&self, connect: &RemConnect, _: SocketAddr) -> Result<(), Reject> {
+        self(connect)
+    }
+}
+
+/// A subscriber currently registered at a `CentralexServer`.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Registration {
+    pub number: u32,
+    /// the address of the subscriber's link
+    pub source: SocketAddr,
+    /// the public port accepting calls for the subscriber
+    pub port: u16,
+}
+
+struct Subscriber {
+    id: u64,
+    registration: Registration,
+    link: TcpStream,
+}
+
+/// The relay side of centralex, accepting calls on behalf of subscribers that
+/// can not be reached directly.
+///
+/// Every subscriber gets its own public port while it is registered. A call
+/// to that port is announced to the subscriber with a `RemCall` and, once it
+/// answered with `RemAck`, connected to the subscriber's link.
+pub struct CentralexServer<A> {
+    authenticator: A,
+    public_address: Option<IpAddr>,
+    ports: Option<RangeInclusive<u16>>,
+    heartbeat_interval: Duration,
+    timeout: Duration,
+    subscribers: Mutex<HashMap<u32, Subscriber>>,
+    next_id: AtomicU64,
+}
+
+impl<A: Authenticator> CentralexServer<A> {
+    pub fn new(authenticator: A) -> Self {
+        CentralexServer {
+            authenticator,
+            public_address: None,
+            ports: None,
+            heartbeat_interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(60),
+            subscribers: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Accept calls for subscribers on `address` instead of all IPv4 and IPv6
+    /// addresses.
+    pub fn with_public_address(mut self, address: IpAddr) -> Self {
+        self.public_address = Some(address);
+        self
+    }
+
+    /// Give subscribers ports from `ports` instead of any free port.
+    pub fn with_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.ports = Some(ports);
+        self
+    }
+
+    /// Send a `Heartbeat` every `interval` and drop subscribers that stay
+    /// silent for `timeout`.
+    pub fn with_heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn authenticator(&self) -> &A {
+        &self.authenticator
+    }
+
+    pub fn registration(&self, number: u32) -> Option<Registration> {
+        self.subscribers()
+            .get(&number)
+            .map(|subscriber| subscriber.registration)
+    }
+
+    pub fn registrations(&self) -> Vec<Registration> {
+        let mut registrations: Vec<Registration> = self
+            .subscribers()
+            .values()
+            .map(|subscriber| subscriber.registration)
+            .collect();
+        registrations.sort_unstable_by_key(|registration| registration.number);
+        registrations
+    }
+
+    fn subscribers(&self) -> MutexGuard<'_, HashMap<u32, Subscriber>> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Serve a subscriber connected to the centralex port, until its link
+    /// breaks or a call to it ended.
+    pub fn serve(&self, mut link: TcpStream) -> std::io::Result<()> {
+        let source = link.peer_addr()?;
+        link.set_read_timeout(Some(self.timeout))?;
+
+        let connect = match Package::<Centralex>::deserialize(&mut link)?.downcast::<RemConnect>() {
+            Some(connect) => *connect,
+            None => return send(&mut link, Reject::from(String::from("expected RemConnect"))),
+        };
+
+        if let Err(reject) = self.authenticator.authenticate(&connect, source) {
+            return send(&mut link, reject);
+        }
+
+        let public = match self.bind_public() {
+            Ok(public) => public,
+            Err(_) => return send(&mut link, Reject::from(String::from("no free port"))),
+        };
+        for listener in &public {
+            listener.set_nonblocking(true)?;
+        }
+
+        let registration = Registration {
+            number: connect.number,
+            source,
+            port: public[0].local_addr()?.port(),
+        };
+        if let Err(reject) = self.authenticator.register(&connect, &registration) {
+            return send(&mut link, reject);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let previous = self.subscribers().insert(
+            connect.number,
+            Subscriber {
+                id,
+                registration,
+                link: link.try_clone()?,
+            },
+        );
+        if let Some(previous) = previous {
+            let _ = previous.link.shutdown(Shutdown::Both);
+        }
+
+        let caller =
+            send(&mut link, RemConfirm {}).and_then(|_| self.wait_for_caller(&mut link, public));
+
+        let mut subscribers = self.subscribers();
+        if subscribers
+            .get(&connect.number)
+            .map(|subscriber| subscriber.id)
+            == Some(id)
+        {
+            subscribers.remove(&connect.number);
+        }
+        drop(subscribers);
+
+        match caller? {
+            Some((caller, address)) => self.call(link, caller, address),
+            None => Ok(()),
+        }
+    }
+
+    /// Accept subscribers on `listener`, serving each one on its own thread.
+    pub fn listen(self: Arc<Self>, listener: TcpListener) -> std::io::Result<()>
+    where
+        A: 'static,
+    {
+        loop {
+            let (stream, _) = match listener.accept() {
+                Ok(connection) => connection,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            let server = self.clone();
+            std::thread::spawn(move || server.serve(stream));
+        }
+    }
+
+    /// Bind the public port of a subscriber, on all listed ports if needed.
+    fn bind_public(&self) -> std::io::Result<Vec<TcpListener>> {
+        let ports = match &self.ports {
+            Some(ports) => ports.clone(),
+            None => return self.bind_public_port(0),
+        };
+
+        let mut last_err = std::io::ErrorKind::AddrInUse.into();
+        for port in ports {
+            match self.bind_public_port(port) {
+                Ok(listeners) => return Ok(listeners),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Bind `port` on the public address, or on all IPv6 and IPv4 addresses.
+    fn bind_public_port(&self, port: u16) -> std::io::Result<Vec<TcpListener>> {
+        if let Some(public_address) = self.public_address {
+            return Ok(vec![TcpListener::bind((public_address, port))?]);
+        }
+
+        let ipv6 = match TcpListener::bind((Ipv6Addr::UNSPECIFIED, port)) {
+            Ok(ipv6) => ipv6,
+            // no IPv6 on this host
+            Err(_) => return Ok(vec![TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?]),
+        };
+
+        match TcpListener::bind((Ipv4Addr::UNSPECIFIED, ipv6.local_addr()?.port())) {
+            Ok(ipv4) => Ok(vec![ipv6, ipv4]),
+            // the IPv6 socket is dual-stack and already accepts IPv4 connections
+            Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => Ok(vec![ipv6]),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Keep the link of a registered subscriber alive until a call arrives on
+    /// `public`, returning `None` if the subscriber unregistered instead.
+    fn wait_for_caller(
+        &self,
+        link: &mut TcpStream,
+        public: Vec<TcpListener>,
+    ) -> std::io::Result<Option<(TcpStream, SocketAddr)>> {
+        let mut last_received = Instant::now();
+        let mut next_heartbeat = Instant::now() + self.heartbeat_interval;
+
+        loop {
+            let now = Instant::now();
+            if now.duration_since(last_received) >= self.timeout {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "subscriber stopped sending heartbeats",
+                ));
+            }
+            if now >= next_heartbeat {
+                send(link, Heartbeat {})?;
+                next_heartbeat = now + self.heartbeat_interval;
+            }
+
+            let wake = next_heartbeat.min(last_received + self.timeout);
+            match wait(link, &public, wake.saturating_duration_since(now))? {
+                Ready::Caller(caller, address) => {
+                    caller.set_nonblocking(false)?;
+                    return Ok(Some((caller, address)));
+                }
+                Ready::Link => {}
+                Ready::Timeout => continue,
+            }
+
+            link.set_read_timeout(Some(self.timeout))?;
+            let package = Package::<Centralex>::deserialize(link)?;
+            last_received = Instant::now();
+
+            match package.package_type() {
+                Centralex::Heartbeat => {}
+                Centralex::End => return Ok(None),
+                _ => {
+                    send(link, Reject::from(String::from("unexpected package")))?;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Announce a call from `address` to the subscriber and splice the streams
+    /// once it accepted.
+    fn call(
+        &self,
+        mut link: TcpStream,
+        caller: TcpStream,
+        address: SocketAddr,
+    ) -> std::io::Result<()> {
+        send(&mut link, RemCall::from(address))?;
+
+        link.set_read_timeout(Some(self.timeout))?;
+        loop {
+            let package = Package::<Centralex>::deserialize(&mut link)?;
+            match package.package_type() {
+                Centralex::Heartbeat => {}
+                Centralex::RemAck => break,
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionRefused,
+                        "subscriber did not accept the call",
+                    ))
+                }
+            }
+        }
+
+        link.set_read_timeout(None)?;
+        splice(link, caller)
+    }
+}
+
+/// Copy data between `a` and `b` in both directions until both sides are done.
+pub fn splice(a: TcpStream, b: TcpStream) -> std::io::Result<()> {
+    let (mut a_reader, mut a_writer) = (a.try_clone()?, a);
+    let (mut b_reader, mut b_writer) = (b.try_clone()?, b);
+
+    let forward = std::thread::spawn(move || {
+        let result = std::io::copy(&mut a_reader, &mut b_writer);
+        let _ = b_writer.shutdown(Shutdown::Write);
+        result
+    });
+
+    let result = std::io::copy(&mut b_reader, &mut a_writer);
+    let _ = a_writer.shutdown(Shutdown::Write);
+
+    forward
+        .join()
+        .map_err(|_| std::io::Error::other("splice thread panicked"))??;
+    result.map(|_| ())
+}
+
+/// What `wait` found first.
+enum Ready {
+    /// a package (or the end) of the link can be read
+    Link,
+    /// a caller was accepted on one of the public listeners
+    Caller(TcpStream, SocketAddr),
+    Timeout,
+}
+
+/// Wait up to `timeout` for input on `link` or a caller on one of `public`.
+#[cfg(unix)]
+fn wait(link: &TcpStream, public: &[TcpListener], timeout: Duration) -> std::io::Result<Ready> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut fds: Vec<libc::pollfd> = std::iter::once(link.as_raw_fd())
+        .chain(public.iter().map(AsRawFd::as_raw_fd))
+        .map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+
+    // round up, so that we don't wake up just before `timeout` over and over
+    let timeout = timeout
+        .as_nanos()
+        .div_ceil(1_000_000)
+        .min(libc::c_int::MAX as u128);
+
+    // SAFETY: `fds` points to `fds.len()` initialized `pollfd`s, which stay
+    // borrowed for the duration of the call
+    let ready = unsafe {
+        libc::poll(
+            fds.as_mut_ptr(),
+            fds.len() as libc::nfds_t,
+            timeout as libc::c_int,
+        )
+    };
+    if ready < 0 {
+        let err = std::io::Error::last_os_error();
+        return if err.kind() == std::io::ErrorKind::Interrupted {
+            Ok(Ready::Timeout)
+        } else {
+            Err(err)
+        };
+    }
+
+    if fds[0].revents != 0 {
+        return Ok(Ready::Link);
+    }
+    for (fd, listener) in fds[1..].iter().zip(public) {
+        if fd.revents != 0 {
+            return accept(listener);
+        }
+    }
+    Ok(Ready::Timeout)
+}
+
+/// Wait up to `timeout` for input on `link` or a caller on one of `public`.
+///
+/// Without a way to wait for both at once, this waits for the link for at
+/// most `POLL_INTERVAL` and then checks the listeners.
+#[cfg(not(unix))]
+fn wait(link: &TcpStream, public: &[TcpListener], timeout: Duration) -> std::io::Result<Ready> {
+    if poll(
+        link,
+        timeout.min(POLL_INTERVAL).max(Duration::from_millis(1)),
+    )? {
+        return Ok(Ready::Link);
+    }
+
+    for listener in public {
+        match accept(listener)? {
+            Ready::Timeout => {}
+            ready => return Ok(ready),
+        }
+    }
+    Ok(Ready::Timeout)
+}
+
+/// Accept a caller on the nonblocking `listener`, if there is one.
+fn accept(listener: &TcpListener) -> std::io::Result<Ready> {
+    match listener.accept() {
+        Ok((caller, address)) => Ok(Ready::Caller(caller, address)),
+        // the caller gave up before it was accepted
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(Ready::Timeout),
+        Err(err) => Err(err),
+    }
+}
+
+/// Wait up to `timeout` for input on `stream`, without consuming any.
+#[cfg(not(unix))]
+fn poll(stream: &TcpStream, timeout: Duration) -> std::io::Result<bool> {
+    stream.set_read_timeout(Some(timeout))?;
+
+    match stream.peek(&mut [0]) {
+        Ok(0) => Err(std::io::ErrorKind::UnexpectedEof.into()),
+        Ok(_) => Ok(true),
+        Err(err)
+            if err.kind() == std::io::ErrorKind::WouldBlock
+                || err.kind() == std::io::ErrorKind::TimedOut =>
+        {
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn send(stream: &mut TcpStream, package: impl Into<Package<Centralex>>) -> std::io::Result<()> {
+    let mut buffer = Vec::new();
+    package.into().serialize(&mut buffer)?;
+
+    stream.write_all(&buffer)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn authenticator() -> impl Authenticator {
+        |connect: &RemConnect| {
+            if connect.pin == 4711 {
+                Ok(())
+            } else {
+                Err(Reject::from(String::from("wrong pin")))
+            }
+        }
+    }
+
+    fn start_server() -> (Arc<CentralexServer<impl Authenticator>>, SocketAddr) {
+        start(CentralexServer::new(authenticator()).with_public_address(Ipv4Addr::LOCALHOST.into()))
+    }
+
+    fn start<A: Authenticator + 'static>(
+        server: CentralexServer<A>,
+    ) -> (Arc<CentralexServer<A>>, SocketAddr) {
+        let server = Arc::new(server);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn({
+            let server = server.clone();
+            move || server.listen(listener)
+        });
+
+        (server, address)
+    }
+
+    #[test]
+    fn rejects_wrong_pin() {
+        let (server, address) = start_server();
+
+        let err = CentralexClient::connect(address, 1234, 1).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(server.registration(1234), None);
+    }
+
+    #[test]
+    fn authenticates_before_binding() {
+        // the only public port is taken, so only authenticated subscribers
+        // find out that there is no free port
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let (_, address) = start(
+            CentralexServer::new(authenticator())
+                .with_public_address(Ipv4Addr::LOCALHOST.into())
+                .with_ports(port..=port),
+        );
+
+        let err = CentralexClient::connect(address, 1234, 1).unwrap_err();
+        assert_eq!(err.to_string(), "wrong pin");
+
+        let err = CentralexClient::connect(address, 1234, 4711).unwrap_err();
+        assert_eq!(err.to_string(), "no free port");
+    }
+
+    #[test]
+    fn accepts_ipv6_callers() {
+        let (server, address) = start(CentralexServer::new(authenticator()));
+
+        let client = CentralexClient::connect(address, 1234, 4711).unwrap();
+        let call = std::thread::spawn(move || client.wait_for_call());
+
+        let registration = wait_for_registration(&server, 1234);
+        let _caller = TcpStream::connect((Ipv6Addr::LOCALHOST, registration.port)).unwrap();
+
+        let call = call.join().unwrap().unwrap();
+        assert_eq!(call.caller.caller(), Some(Ipv6Addr::LOCALHOST.into()));
+    }
+
+    fn wait_for_registration(
+        server: &CentralexServer<impl Authenticator>,
+        number: u32,
+    ) -> Registration {
+        for _ in 0..100 {
+            if let Some(registration) = server.registration(number) {
+                return registration;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("{} did not register", number);
+    }
+
+    #[test]
+    fn relays_calls() {
+        let (server, address) = start_server();
+
+        let client = CentralexClient::connect(address, 1234, 4711).unwrap();
+        let call = std::thread::spawn(move || client.wait_for_call());
+
+        let registration = wait_for_registration(&server, 1234);
+        assert_eq!(server.registrations(), vec![registration]);
+
+        let mut caller = TcpStream::connect((Ipv4Addr::LOCALHOST, registration.port)).unwrap();
+        caller.write_all(b"hello").unwrap();
+
+        let mut call = call.join().unwrap().unwrap();
+        assert_eq!(call.caller.caller(), Some(Ipv4Addr::LOCALHOST.into()));
+
+        let mut data = [0; 5];
+        call.stream.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"hello");
+
+        call.stream.write_all(b"back").unwrap();
+        drop(call);
+
+        let mut data = Vec::new();
+        caller.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"back");
+
+        assert_eq!(server.registration(1234), None);
+    }
+}
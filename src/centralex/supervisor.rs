@@ -0,0 +1,462 @@
+use super::*;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Exponentially growing delays between reconnection attempts.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    jitter: f64,
+    attempt: u32,
+    random: u64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(5 * 60))
+    }
+}
+
+impl Backoff {
+    /// Start with `initial`, doubling the delay on every attempt up to `max`.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Backoff {
+            initial,
+            max,
+            jitter: 0.5,
+            attempt: 0,
+            // xorshift gets stuck on zero
+            random: seed | 1,
+        }
+    }
+
+    /// Shorten each delay by a random part of up to `jitter` (between 0 and 1)
+    /// of it, so that many clients losing their links at once do not all
+    /// reconnect at the same time.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .initial
+            .checked_mul(1 << self.attempt.min(31))
+            .map_or(self.max, |delay| delay.min(self.max));
+        self.attempt = self.attempt.saturating_add(1);
+
+        delay.mul_f64(1.0 - self.jitter * self.random())
+    }
+
+    /// Start over with the initial delay, after a successful attempt.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// A number between 0 and 1.
+    fn random(&mut self) -> f64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+
+        (self.random >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A change of the state of a supervised centralex link.
+#[derive(Debug)]
+pub enum LinkEvent {
+    Connecting,
+    /// the centralex server accepted the registration
+    Connected,
+    /// a call was forwarded, a new link is established right away
+    Call(IncomingCall),
+    /// the link failed or could not be established
+    Disconnected(std::io::Error),
+    /// the next attempt is made after this delay
+    Retrying(Duration),
+    /// the registration was refused for good, the supervisor stops
+    Rejected(Reject),
+}
+
+/// Stops a running `Supervisor`.
+#[derive(Debug, Clone)]
+pub struct StopHandle {
+    stopped: Arc<AtomicBool>,
+    link: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+
+        if let Some(link) = &*self.link.lock().unwrap_or_else(|err| err.into_inner()) {
+            let _ = link.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+/// Keeps a centralex registration up, reconnecting whenever the link fails.
+///
+/// Rejects for reasons that will not go away by themselves, like a wrong pin,
+/// end the supervision, every other failure is retried with `Backoff`. As
+/// servers word their rejects differently, rejects for reasons not known to be
+/// temporary also end it once `max_rejects` of them came in a row.
+#[derive(Debug)]
+pub struct Supervisor {
+    address: String,
+    number: u32,
+    pin: u16,
+    heartbeat: Option<(Duration, Duration)>,
+    backoff: Backoff,
+    stable_after: Duration,
+    max_rejects: u32,
+    stop: StopHandle,
+}
+
+impl Supervisor {
+    pub fn new(address: impl Into<String>, number: u32, pin: u16) -> Self {
+        Supervisor {
+            address: address.into(),
+            number,
+            pin,
+            heartbeat: None,
+            backoff: Backoff::default(),
+            stable_after: Duration::from_secs(60),
+            max_rejects: 3,
+            stop: StopHandle {
+                stopped: Arc::new(AtomicBool::new(false)),
+                link: Arc::new(Mutex::new(None)),
+            },
+        }
+    }
+
+    /// See `CentralexClient::with_heartbeat`.
+    pub fn with_heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.heartbeat = Some((interval, timeout));
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Only start over with the initial delay of the `Backoff` once a link
+    /// stayed up for `stable_after`, so that links dropping right after
+    /// registering are not retried in a tight loop.
+    pub fn with_stable_after(mut self, stable_after: Duration) -> Self {
+        self.stable_after = stable_after;
+        self
+    }
+
+    /// Give up after `max_rejects` rejects in a row for reasons not known to
+    /// be temporary.
+    pub fn with_max_rejects(mut self, max_rejects: u32) -> Self {
+        self.max_rejects = max_rejects.max(1);
+        self
+    }
+
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Run on a new thread, reporting events through the returned channel.
+    pub fn spawn(self) -> (JoinHandle<Result<(), Reject>>, Receiver<LinkEvent>) {
+        let (sender, receiver) = channel();
+        (std::thread::spawn(move || self.run(&sender)), receiver)
+    }
+
+    /// Keep the link up until it is stopped, nobody receives `events` any
+    /// more, or the registration is rejected for good.
+    pub fn run(mut self, events: &Sender<LinkEvent>) -> Result<(), Reject> {
+        let mut rejects = 0;
+
+        loop {
+            if self.stop.is_stopped() || events.send(LinkEvent::Connecting).is_err() {
+                return Ok(());
+            }
+
+            let event = match self.connect() {
+                Ok(client) => {
+                    rejects = 0;
+                    if events.send(LinkEvent::Connected).is_err() {
+                        return Ok(());
+                    }
+
+                    let connected = Instant::now();
+                    let result = client.wait_for_call();
+                    *self.stop.link.lock().unwrap_or_else(|err| err.into_inner()) = None;
+                    if connected.elapsed() >= self.stable_after {
+                        self.backoff.reset();
+                    }
+
+                    match result {
+                        Ok(call) => {
+                            if events.send(LinkEvent::Call(call)).is_err() {
+                                return Ok(());
+                            }
+                            continue;
+                        }
+                        Err(err) => LinkEvent::Disconnected(err),
+                    }
+                }
+                Err(err) => {
+                    let reject = match reject(&err) {
+                        Some(reject) => {
+                            let reason = reject.reason();
+                            if !matches!(
+                                reason,
+                                RejectReason::Unavailable | RejectReason::TooManyFailures
+                            ) {
+                                rejects += 1;
+                            }
+                            Some(reject)
+                                .filter(|_| reason.is_permanent() || rejects >= self.max_rejects)
+                        }
+                        None => {
+                            rejects = 0;
+                            None
+                        }
+                    };
+
+                    match reject {
+                        Some(reject) => {
+                            let _ = events.send(LinkEvent::Rejected(reject.clone()));
+                            return Err(reject);
+                        }
+                        None => LinkEvent::Disconnected(err),
+                    }
+                }
+            };
+
+            if self.stop.is_stopped() || events.send(event).is_err() {
+                return Ok(());
+            }
+
+            let delay = self.backoff.next_delay();
+            if events.send(LinkEvent::Retrying(delay)).is_err() {
+                return Ok(());
+            }
+            self.sleep(delay);
+        }
+    }
+
+    fn connect(&self) -> std::io::Result<CentralexClient> {
+        let mut client = CentralexClient::connect(self.address.as_str(), self.number, self.pin)?;
+        if let Some((interval, timeout)) = self.heartbeat {
+            client = client.with_heartbeat(interval, timeout);
+        }
+
+        *self.stop.link.lock().unwrap_or_else(|err| err.into_inner()) =
+            Some(client.stream().try_clone()?);
+        if self.stop.is_stopped() {
+            let _ = client.stream().shutdown(Shutdown::Both);
+        }
+
+        Ok(client)
+    }
+
+    /// Wait for `delay`, returning early if stopped.
+    fn sleep(&self, delay: Duration) {
+        let step = Duration::from_millis(100);
+
+        let mut remaining = delay;
+        while remaining > Duration::from_secs(0) && !self.stop.is_stopped() {
+            let step = remaining.min(step);
+            std::thread::sleep(step);
+            remaining -= step;
+        }
+    }
+}
+
+/// The `Reject` the registration in `err` was answered with, if any.
+fn reject(err: &std::io::Error) -> Option<Reject> {
+    if err.kind() != std::io::ErrorKind::PermissionDenied {
+        return None;
+    }
+
+    err.get_ref()
+        .and_then(|err| err.downcast_ref::<Reject>())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddr, TcpListener};
+
+    #[test]
+    fn backoff() {
+        let mut backoff =
+            Backoff::new(Duration::from_secs(1), Duration::from_secs(10)).with_jitter(0.0);
+        let delays: Vec<u64> = (0..6).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+
+        let mut backoff = Backoff::new(Duration::from_secs(8), Duration::from_secs(8));
+        for _ in 0..100 {
+            let delay = backoff.next_delay();
+            assert!(delay >= Duration::from_secs(4) && delay <= Duration::from_secs(8));
+        }
+    }
+
+    #[test]
+    fn reconnects_until_rejected() {
+        // closing the link right away makes the registered client reconnect
+        let address = start_server(vec![
+            Package::<Centralex>::new(Reject::from(RejectReason::Unavailable)),
+            RemConfirm {}.into(),
+            Reject::from(RejectReason::WrongPin).into(),
+        ]);
+
+        let supervisor = Supervisor::new(address.to_string(), 1234, 4711).with_backoff(
+            Backoff::new(Duration::from_millis(10), Duration::from_millis(10)).with_jitter(0.0),
+        );
+        let (handle, events) = supervisor.spawn();
+
+        let reject = handle.join().unwrap().unwrap_err();
+        assert_eq!(reject.reason(), RejectReason::WrongPin);
+
+        let events: Vec<String> = events
+            .iter()
+            .map(|event| match event {
+                LinkEvent::Connecting => "connecting",
+                LinkEvent::Connected => "connected",
+                LinkEvent::Call(_) => "call",
+                LinkEvent::Disconnected(_) => "disconnected",
+                LinkEvent::Retrying(_) => "retrying",
+                LinkEvent::Rejected(_) => "rejected",
+            })
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                "connecting",
+                "disconnected",
+                "retrying",
+                "connecting",
+                "connected",
+                "disconnected",
+                "retrying",
+                "connecting",
+                "rejected"
+            ]
+        );
+    }
+
+    /// Start a centralex server answering each registration with the next
+    /// of `replies` and closing the link right away, until none are left.
+    fn start_server(replies: Vec<Package<Centralex>>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let mut replies = replies.into_iter();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                Package::<Centralex>::deserialize(&mut stream).unwrap();
+
+                match replies.next() {
+                    Some(reply) => reply.serialize(&mut stream).unwrap(),
+                    None => return,
+                }
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn gives_up_on_repeated_rejects() {
+        let unknown = || Package::<Centralex>::new(Reject::from(String::from("falsche PIN")));
+        let address = start_server(vec![
+            unknown(),
+            Reject::from(RejectReason::Unavailable).into(),
+            unknown(),
+            unknown(),
+        ]);
+
+        let supervisor = Supervisor::new(address.to_string(), 1234, 4711).with_backoff(
+            Backoff::new(Duration::from_millis(10), Duration::from_millis(10)).with_jitter(0.0),
+        );
+        let (handle, events) = supervisor.spawn();
+
+        let reject = handle.join().unwrap().unwrap_err();
+        assert_eq!(reject.message, "falsche PIN");
+        let attempts = events
+            .iter()
+            .filter(|event| matches!(event, LinkEvent::Connecting))
+            .count();
+        assert_eq!(attempts, 4);
+    }
+
+    #[test]
+    fn backs_off_from_unstable_links() {
+        let address = start_server(vec![
+            RemConfirm {}.into(),
+            RemConfirm {}.into(),
+            RemConfirm {}.into(),
+            Reject::from(RejectReason::WrongPin).into(),
+        ]);
+
+        let supervisor = Supervisor::new(address.to_string(), 1234, 4711).with_backoff(
+            Backoff::new(Duration::from_millis(10), Duration::from_secs(1)).with_jitter(0.0),
+        );
+        let (handle, events) = supervisor.spawn();
+        handle.join().unwrap().unwrap_err();
+
+        let delays: Vec<u128> = events
+            .iter()
+            .filter_map(|event| match event {
+                LinkEvent::Retrying(delay) => Some(delay.as_millis()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(delays, vec![10, 20, 40]);
+    }
+
+    #[test]
+    fn stops() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let mut streams = Vec::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                Package::<Centralex>::deserialize(&mut stream).unwrap();
+                RemConfirm {}.to_package().serialize(&mut stream).unwrap();
+                streams.push(stream);
+            }
+        });
+
+        let supervisor = Supervisor::new(address.to_string(), 1234, 4711);
+        let stop = supervisor.stop_handle();
+        let (handle, events) = supervisor.spawn();
+
+        assert!(matches!(events.recv().unwrap(), LinkEvent::Connecting));
+        assert!(matches!(events.recv().unwrap(), LinkEvent::Connected));
+
+        stop.stop();
+        assert!(handle.join().unwrap().is_ok());
+    }
+}
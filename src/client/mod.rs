@@ -1,9 +1,23 @@
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+use crate::io;
+use crate::wire::{Decode, Encode};
+use crate::Extension;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
+pub struct DirectDial {
+    pub extension: Extension,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct End {}
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct Heartbeat {}
@@ -15,26 +29,32 @@ pub struct Reject {
     pub message: String,
 }
 
-impl<W: std::io::Write> binserde::Serialize<W> for Reject {
-    fn serialize_ne(&self, writer: &mut W) -> std::io::Result<()> {
+wire_struct! {
+    DirectDial { extension }
+    End {}
+    Heartbeat {}
+}
+
+impl Encode for Reject {
+    fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
         writer.write_all(self.message.as_bytes())?;
         writer.write_all(&[0])?;
 
         Ok(())
     }
 }
-impl<R: std::io::Read> binserde::Deserialize<R> for Reject {
-    fn deserialize_ne(reader: &mut R) -> std::io::Result<Self> {
+impl Decode for Reject {
+    fn decode(reader: &mut impl io::Read) -> io::Result<Self> {
         let mut buffer = Vec::new();
         loop {
-            let byte = u8::deserialize_ne(reader)?;
+            let byte = u8::decode(reader)?;
 
             if byte != 0 {
                 buffer.push(byte);
             } else {
                 return Ok(Reject {
                     message: String::from_utf8(buffer)
-                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?,
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
                 });
             }
         }
@@ -47,17 +67,17 @@ impl From<String> for Reject {
     }
 }
 
-impl std::fmt::Display for Reject {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for Reject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.message)
     }
 }
 
-impl std::error::Error for Reject {}
+impl core::error::Error for Reject {}
 
-#[non_exhaustive] // TODO: remove once complete
 package_class! {Client("Client"),
     Heartbeat = 0x00,
+    DirectDial = 0x01,
     End = 0x03,
     Reject = 0x04,
     // TODO
@@ -0,0 +1,388 @@
+//! Decoding of captured byte streams for debugging, reporting every package
+//! with its position and continuing after packages that fail to decode.
+//!
+//! Packet captures are split into their TCP sessions, which are decoded
+//! according to the protocol they are found to speak.
+
+#[cfg(feature = "centralex")]
+use crate::centralex::Centralex;
+#[cfg(feature = "client")]
+use crate::client::Client;
+#[cfg(feature = "server")]
+use crate::server::Server;
+use crate::Package;
+use std::convert::TryFrom;
+
+#[cfg(all(feature = "server", feature = "centralex", feature = "client"))]
+pub mod pcap;
+#[cfg(all(feature = "server", feature = "centralex", feature = "client"))]
+mod session;
+#[cfg(all(feature = "server", feature = "centralex", feature = "client"))]
+pub use session::*;
+
+/// The package classes a stream can be decoded as.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum PackageClass {
+    #[cfg(feature = "server")]
+    Server,
+    #[cfg(feature = "centralex")]
+    Centralex,
+    #[cfg(feature = "client")]
+    Client,
+}
+
+impl PackageClass {
+    /// All enabled classes, in the order they are tried when auto-detecting.
+    pub const ALL: &'static [PackageClass] = &[
+        #[cfg(feature = "server")]
+        PackageClass::Server,
+        #[cfg(feature = "centralex")]
+        PackageClass::Centralex,
+        #[cfg(feature = "client")]
+        PackageClass::Client,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "server")]
+            PackageClass::Server => "server",
+            #[cfg(feature = "centralex")]
+            PackageClass::Centralex => "centralex",
+            #[cfg(feature = "client")]
+            PackageClass::Client => "client",
+        }
+    }
+
+    /// Decode a single package, header included, from `frame`.
+    ///
+    /// Fails if the body is longer than what the package type needs.
+    pub fn decode(self, frame: &[u8]) -> std::io::Result<Decoded> {
+        let decoded = self.decode_package(frame)?;
+
+        let mut encoded = Vec::new();
+        decoded.serialize(&mut encoded)?;
+        if encoded.len() < frame.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{:?} leaves {} bytes of the body unused",
+                    decoded,
+                    frame.len() - encoded.len()
+                ),
+            ));
+        }
+
+        Ok(decoded)
+    }
+
+    fn decode_package(self, mut frame: &[u8]) -> std::io::Result<Decoded> {
+        let package_type = frame.first().copied().unwrap_or_default();
+        let unknown = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unknown package type 0x{:02x}", package_type),
+            )
+        };
+
+        match self {
+            #[cfg(feature = "server")]
+            PackageClass::Server => {
+                Server::try_from(package_type).map_err(|_| unknown())?;
+                Package::<Server>::deserialize(&mut frame).map(Decoded::Server)
+            }
+            #[cfg(feature = "centralex")]
+            PackageClass::Centralex => {
+                Centralex::try_from(package_type).map_err(|_| unknown())?;
+                Package::<Centralex>::deserialize(&mut frame).map(Decoded::Centralex)
+            }
+            #[cfg(feature = "client")]
+            PackageClass::Client => {
+                Client::try_from(package_type).map_err(|_| unknown())?;
+                Package::<Client>::deserialize(&mut frame).map(Decoded::Client)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PackageClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for PackageClass {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        PackageClass::ALL
+            .iter()
+            .copied()
+            .find(|class| class.name().eq_ignore_ascii_case(string))
+            .ok_or_else(|| format!("unknown package class {:?}", string))
+    }
+}
+
+/// A successfully decoded package of any class.
+pub enum Decoded {
+    #[cfg(feature = "server")]
+    Server(Package<Server>),
+    #[cfg(feature = "centralex")]
+    Centralex(Package<Centralex>),
+    #[cfg(feature = "client")]
+    Client(Package<Client>),
+}
+
+impl Decoded {
+    pub fn class(&self) -> PackageClass {
+        match self {
+            #[cfg(feature = "server")]
+            Decoded::Server(_) => PackageClass::Server,
+            #[cfg(feature = "centralex")]
+            Decoded::Centralex(_) => PackageClass::Centralex,
+            #[cfg(feature = "client")]
+            Decoded::Client(_) => PackageClass::Client,
+        }
+    }
+
+    pub fn serialize(&self, writer: &mut impl std::io::Write) -> std::io::Result<()> {
+        match self {
+            #[cfg(feature = "server")]
+            Decoded::Server(package) => package.serialize(writer),
+            #[cfg(feature = "centralex")]
+            Decoded::Centralex(package) => package.serialize(writer),
+            #[cfg(feature = "client")]
+            Decoded::Client(package) => package.serialize(writer),
+        }
+    }
+}
+
+impl std::fmt::Debug for Decoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "server")]
+            Decoded::Server(package) => package.fmt(f),
+            #[cfg(feature = "centralex")]
+            Decoded::Centralex(package) => package.fmt(f),
+            #[cfg(feature = "client")]
+            Decoded::Client(package) => package.fmt(f),
+        }
+    }
+}
+
+/// A package found in a stream.
+#[derive(Debug)]
+pub struct Frame {
+    /// the position of the header in the stream
+    pub offset: usize,
+    pub package_type: u8,
+    /// the length of the body as given by the header
+    pub length: u8,
+    /// the bytes of the frame, header included, as far as they are available
+    pub bytes: Vec<u8>,
+    pub decoded: Result<Decoded, String>,
+}
+
+impl Frame {
+    /// Whether the stream ended before the end of this frame.
+    pub fn is_truncated(&self) -> bool {
+        self.bytes.len() < 2 + self.length as usize
+    }
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:06x}  type 0x{:02x}  length {:3}  ",
+            self.offset, self.package_type, self.length
+        )?;
+
+        match &self.decoded {
+            Ok(decoded) => write!(f, "{}: {:?}", decoded.class(), decoded),
+            Err(err) => write!(f, "error: {}", err),
+        }
+    }
+}
+
+/// Split `bytes` into packages and decode them as `class`, or as whichever
+/// class fits if `class` is `None`.
+///
+/// Auto-detection tries the class of the previous package first, so that
+/// packages existing in several classes are attributed consistently.
+pub fn dissect(bytes: &[u8], class: Option<PackageClass>) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    let mut previous = None;
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        if rest.len() < 2 {
+            frames.push(Frame {
+                offset,
+                package_type: rest[0],
+                length: 0,
+                bytes: rest.to_vec(),
+                decoded: Err(String::from("truncated header")),
+            });
+            break;
+        }
+
+        let (package_type, length) = (rest[0], rest[1]);
+        let end = (2 + length as usize).min(rest.len());
+        let frame = &rest[..end];
+
+        let decoded = if end < 2 + length as usize {
+            Err(format!(
+                "truncated, {} of {} bytes of the body present",
+                end - 2,
+                length
+            ))
+        } else {
+            match class {
+                Some(class) => class.decode(frame).map_err(|err| err.to_string()),
+                None => decode_any(frame, previous),
+            }
+        };
+
+        if let Ok(decoded) = &decoded {
+            previous = Some(decoded.class());
+        }
+
+        frames.push(Frame {
+            offset,
+            package_type,
+            length,
+            bytes: frame.to_vec(),
+            decoded,
+        });
+
+        offset += end;
+    }
+
+    frames
+}
+
+fn decode_any(frame: &[u8], preferred: Option<PackageClass>) -> Result<Decoded, String> {
+    let classes = preferred.into_iter().chain(
+        PackageClass::ALL
+            .iter()
+            .copied()
+            .filter(|class| Some(*class) != preferred),
+    );
+
+    let mut errors = Vec::new();
+    for class in classes {
+        match class.decode(frame) {
+            Ok(decoded) => return Ok(decoded),
+            Err(err) => errors.push(format!("{}: {}", class, err)),
+        }
+    }
+
+    Err(errors.join(", "))
+}
+
+/// Parse hex text like `05 64 d2 04`, `0x05,0x64` or `0564d204`, ignoring
+/// whitespace, commas, colons and `0x` prefixes.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = text
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+        .map(|word| word.strip_prefix("0x").unwrap_or(word))
+        .flat_map(str::bytes)
+        .collect();
+
+    let pairs = digits.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(String::from("odd number of hex digits"));
+    }
+
+    pairs
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).map_err(|err| err.to_string())?;
+            u8::from_str_radix(pair, 16).map_err(|_| format!("invalid hex digits {:?}", pair))
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "server", feature = "centralex", feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::server::{PeerNotFound, PeerQuery};
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_hex("05 64 d2 04"), Ok(vec![0x05, 0x64, 0xd2, 0x04]));
+        assert_eq!(
+            parse_hex("0x05,0x64\n0xD2:04"),
+            Ok(vec![0x05, 0x64, 0xd2, 0x04])
+        );
+        assert!(parse_hex("056").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[test]
+    fn dissects() {
+        let mut bytes = Vec::new();
+        Package::<Server>::new(PeerQuery {
+            number: 1234,
+            version: 1,
+        })
+        .serialize(&mut bytes)
+        .unwrap();
+        // an unknown package type
+        bytes.extend_from_slice(&[0x42, 0x01, 0x00]);
+        Package::<Server>::new(PeerNotFound {})
+            .serialize(&mut bytes)
+            .unwrap();
+        // a PeerQuery cut short
+        bytes.extend_from_slice(&[0x03, 0x05, 0x01]);
+
+        let frames = dissect(&bytes, Some(PackageClass::Server));
+        assert_eq!(
+            frames.iter().map(|frame| frame.offset).collect::<Vec<_>>(),
+            vec![0, 7, 10, 12]
+        );
+        assert!(frames[0].decoded.is_ok());
+        assert!(frames[1].decoded.is_err());
+        assert!(frames[2].decoded.is_ok());
+        assert!(frames[3].is_truncated());
+
+        assert!(frames[0]
+            .to_string()
+            .starts_with("000000  type 0x03  length   5  server: "));
+
+        let frames = dissect(&[0x03, 0x01, 0x00], Some(PackageClass::Centralex));
+        assert_eq!(
+            frames[0].decoded.as_ref().unwrap_err(),
+            "Package<Centralex>(End) leaves 1 bytes of the body unused"
+        );
+    }
+
+    #[test]
+    fn detects_class() {
+        let mut bytes = Vec::new();
+        Package::<Centralex>::new(crate::centralex::RemAck {})
+            .serialize(&mut bytes)
+            .unwrap();
+        Package::<Centralex>::new(crate::centralex::Heartbeat {})
+            .serialize(&mut bytes)
+            .unwrap();
+        Package::<Client>::new(crate::client::DirectDial {
+            extension: crate::Extension::NONE,
+        })
+        .serialize(&mut bytes)
+        .unwrap();
+
+        let classes: Vec<PackageClass> = dissect(&bytes, None)
+            .into_iter()
+            .map(|frame| frame.decoded.unwrap().class())
+            .collect();
+        assert_eq!(
+            classes,
+            vec![
+                PackageClass::Centralex,
+                PackageClass::Centralex,
+                PackageClass::Client
+            ]
+        );
+    }
+}
@@ -0,0 +1,468 @@
+//! Reading of the TCP segments in pcap and pcapng capture files.
+//!
+//! Only what is needed to get at TCP payloads is understood: Ethernet (with
+//! VLAN tags), Linux cooked, loopback and raw IP link layers, unfragmented
+//! IPv4 and IPv6 packets and TCP headers.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER: u32 = 0x1A2B_3C4D;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const PROTOCOL_TCP: u8 = 6;
+
+/// A TCP segment found in a capture.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Segment {
+    /// the capture time, since the unix epoch
+    pub time: Duration,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub sequence: u32,
+    pub flags: TcpFlags,
+    pub payload: Vec<u8>,
+}
+
+/// The TCP flags relevant for reassembly.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct TcpFlags {
+    pub syn: bool,
+    pub ack: bool,
+    pub fin: bool,
+    pub rst: bool,
+}
+
+/// Whether `bytes` start like a pcap or pcapng file.
+pub fn is_capture(bytes: &[u8]) -> bool {
+    pcap_format(bytes).is_some()
+        || read_u32(bytes, 0, Endian::Little) == Some(PCAPNG_SECTION_HEADER)
+}
+
+/// Read all TCP segments from a pcap or pcapng file, in capture order.
+///
+/// Packets that are not TCP over IP, or that are cut short by the snapshot
+/// length, are skipped.
+pub fn read_capture(bytes: &[u8]) -> std::io::Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+
+    if let Some((endian, units)) = pcap_format(bytes) {
+        read_pcap(bytes, endian, units, &mut segments)?;
+    } else if read_u32(bytes, 0, Endian::Little) == Some(PCAPNG_SECTION_HEADER) {
+        read_pcapng(bytes, &mut segments)?;
+    } else {
+        return Err(invalid_data("not a pcap or pcapng file"));
+    }
+
+    Ok(segments)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Endian {
+    Little,
+    Big,
+}
+
+fn read_u16(bytes: &[u8], offset: usize, endian: Endian) -> Option<u16> {
+    let bytes = [*bytes.get(offset)?, *bytes.get(offset + 1)?];
+    Some(match endian {
+        Endian::Little => u16::from_le_bytes(bytes),
+        Endian::Big => u16::from_be_bytes(bytes),
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize, endian: Endian) -> Option<u32> {
+    let mut word = [0; 4];
+    word.copy_from_slice(bytes.get(offset..offset + 4)?);
+    Some(match endian {
+        Endian::Little => u32::from_le_bytes(word),
+        Endian::Big => u32::from_be_bytes(word),
+    })
+}
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+/// The byte order and timestamp units per second of a pcap file.
+fn pcap_format(bytes: &[u8]) -> Option<(Endian, u64)> {
+    match read_u32(bytes, 0, Endian::Little)? {
+        0xA1B2_C3D4 => Some((Endian::Little, 1_000_000)),
+        0xA1B2_3C4D => Some((Endian::Little, 1_000_000_000)),
+        0xD4C3_B2A1 => Some((Endian::Big, 1_000_000)),
+        0x4D3C_B2A1 => Some((Endian::Big, 1_000_000_000)),
+        _ => None,
+    }
+}
+
+fn timestamp(seconds: u64, fraction: u64, units: u64) -> Duration {
+    let nanos = u128::from(fraction) * 1_000_000_000 / u128::from(units);
+    Duration::from_secs(seconds) + Duration::from_nanos(nanos as u64)
+}
+
+fn read_pcap(
+    bytes: &[u8],
+    endian: Endian,
+    units: u64,
+    segments: &mut Vec<Segment>,
+) -> std::io::Result<()> {
+    // the upper bits may hold the FCS length
+    let link_type = read_u32(bytes, 20, endian)
+        .ok_or_else(|| invalid_data("truncated pcap header"))?
+        & 0x0FFF_FFFF;
+
+    let mut offset = 24;
+    while offset < bytes.len() {
+        let field = |index: usize| read_u32(bytes, offset + 4 * index, endian);
+        let (seconds, fraction, length) = match (field(0), field(1), field(2)) {
+            (Some(seconds), Some(fraction), Some(length)) => (seconds, fraction, length as usize),
+            _ => return Err(invalid_data("truncated pcap record header")),
+        };
+
+        let data = bytes
+            .get(offset + 16..offset + 16 + length)
+            .ok_or_else(|| invalid_data("truncated pcap record"))?;
+        let fraction = u64::from(fraction).min(units - 1);
+        let time = timestamp(u64::from(seconds), fraction, units);
+        segments.extend(parse_link(link_type, data, time));
+
+        offset += 16 + length;
+    }
+
+    Ok(())
+}
+
+/// An interface described in a pcapng section.
+struct Interface {
+    link_type: u32,
+    /// timestamp units per second
+    units: u64,
+}
+
+fn read_pcapng(bytes: &[u8], segments: &mut Vec<Segment>) -> std::io::Result<()> {
+    let mut endian = Endian::Little;
+    let mut interfaces = Vec::new();
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let block_type = read_u32(bytes, offset, endian)
+            .ok_or_else(|| invalid_data("truncated pcapng block"))?;
+
+        if block_type == PCAPNG_SECTION_HEADER {
+            endian = match read_u32(bytes, offset + 8, Endian::Little) {
+                Some(PCAPNG_BYTE_ORDER) => Endian::Little,
+                Some(order) if order.swap_bytes() == PCAPNG_BYTE_ORDER => Endian::Big,
+                _ => return Err(invalid_data("invalid pcapng byte order magic")),
+            };
+            interfaces.clear();
+        }
+
+        let length = read_u32(bytes, offset + 4, endian)
+            .ok_or_else(|| invalid_data("truncated pcapng block"))? as usize;
+        if length < 12 || length & 3 != 0 {
+            return Err(invalid_data(format!(
+                "invalid pcapng block length {}",
+                length
+            )));
+        }
+        let body = bytes
+            .get(offset + 8..offset + length - 4)
+            .ok_or_else(|| invalid_data("truncated pcapng block"))?;
+
+        match block_type {
+            // interface description
+            1 => interfaces.push(Interface {
+                link_type: u32::from(read_u16(body, 0, endian).unwrap_or_default()),
+                units: interface_units(body.get(8..).unwrap_or_default(), endian),
+            }),
+            // enhanced packet
+            6 => {
+                let field = |index: usize| read_u32(body, 4 * index, endian);
+                if let (Some(interface), Some(high), Some(low), Some(length)) =
+                    (field(0), field(1), field(2), field(3))
+                {
+                    let interface = interfaces
+                        .get(interface as usize)
+                        .ok_or_else(|| invalid_data("packet of an undescribed interface"))?;
+                    let data = body
+                        .get(20..20 + length as usize)
+                        .ok_or_else(|| invalid_data("truncated pcapng packet"))?;
+
+                    let time = u64::from(high) << 32 | u64::from(low);
+                    let time = timestamp(
+                        time / interface.units,
+                        time % interface.units,
+                        interface.units,
+                    );
+                    segments.extend(parse_link(interface.link_type, data, time));
+                }
+            }
+            // simple packet, without a timestamp
+            3 => {
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| invalid_data("packet of an undescribed interface"))?;
+                let data = body.get(4..).unwrap_or_default();
+                segments.extend(parse_link(interface.link_type, data, Duration::default()));
+            }
+            _ => {}
+        }
+
+        offset += length;
+    }
+
+    Ok(())
+}
+
+/// The `if_tsresol` option of an interface description, microseconds if absent.
+fn interface_units(mut options: &[u8], endian: Endian) -> u64 {
+    while let (Some(code), Some(length)) =
+        (read_u16(options, 0, endian), read_u16(options, 2, endian))
+    {
+        let length = length as usize;
+        match (code, options.get(4)) {
+            (0, _) => break,
+            (9, Some(&resolution)) if length == 1 => {
+                let exponent = u32::from(resolution & 0x7F);
+                let base: u64 = if resolution & 0x80 == 0 { 10 } else { 2 };
+                if let Some(units) = base.checked_pow(exponent) {
+                    return units;
+                }
+            }
+            _ => {}
+        }
+
+        let padded = 4 + ((length + 3) & !3);
+        options = options.get(padded..).unwrap_or_default();
+    }
+
+    1_000_000
+}
+
+/// Strip the link layer header off `data` and parse the IP packet inside.
+fn parse_link(link_type: u32, data: &[u8], time: Duration) -> Option<Segment> {
+    let packet = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = read_u16(data, offset, Endian::Big)?;
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = read_u16(data, offset, Endian::Big)?;
+            }
+            ip_payload(ethertype, data.get(offset + 2..)?)?
+        }
+        LINKTYPE_LINUX_SLL => ip_payload(read_u16(data, 14, Endian::Big)?, data.get(16..)?)?,
+        LINKTYPE_LINUX_SLL2 => ip_payload(read_u16(data, 0, Endian::Big)?, data.get(20..)?)?,
+        // the address family is in host byte order, the ip version tells us enough
+        LINKTYPE_NULL | LINKTYPE_LOOP => data.get(4..)?,
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => data,
+        _ => return None,
+    };
+
+    parse_ip(packet, time)
+}
+
+fn ip_payload(ethertype: u16, data: &[u8]) -> Option<&[u8]> {
+    if ethertype == ETHERTYPE_IPV4 || ethertype == ETHERTYPE_IPV6 {
+        Some(data)
+    } else {
+        None
+    }
+}
+
+fn parse_ip(packet: &[u8], time: Duration) -> Option<Segment> {
+    let (source, destination, segment) = match packet.first()? >> 4 {
+        4 => {
+            let header_length = (packet[0] & 0x0F) as usize * 4;
+            let total_length = read_u16(packet, 2, Endian::Big)? as usize;
+            let fragment = read_u16(packet, 6, Endian::Big)?;
+            // fragments are not reassembled, the first one would be cut short
+            if fragment & 0x3FFF != 0 || *packet.get(9)? != PROTOCOL_TCP {
+                return None;
+            }
+
+            let address = |offset: usize| {
+                let mut octets = [0; 4];
+                octets.copy_from_slice(packet.get(offset..offset + 4)?);
+                Some(IpAddr::from(Ipv4Addr::from(octets)))
+            };
+            (
+                address(12)?,
+                address(16)?,
+                packet.get(header_length..total_length)?,
+            )
+        }
+        6 => {
+            let payload_length = read_u16(packet, 4, Endian::Big)? as usize;
+            let mut next_header = *packet.get(6)?;
+            let mut offset = 40;
+            // skip hop-by-hop, routing and destination options headers
+            while next_header == 0 || next_header == 43 || next_header == 60 {
+                let length = (*packet.get(offset + 1)? as usize + 1) * 8;
+                next_header = *packet.get(offset)?;
+                offset += length;
+            }
+            if next_header != PROTOCOL_TCP {
+                return None;
+            }
+
+            let address = |offset: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(packet.get(offset..offset + 16)?);
+                Some(IpAddr::from(Ipv6Addr::from(octets)))
+            };
+            (
+                address(8)?,
+                address(24)?,
+                packet.get(offset..40 + payload_length)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let header_length = (*segment.get(12)? >> 4) as usize * 4;
+    let flags = *segment.get(13)?;
+
+    Some(Segment {
+        time,
+        source: SocketAddr::new(source, read_u16(segment, 0, Endian::Big)?),
+        destination: SocketAddr::new(destination, read_u16(segment, 2, Endian::Big)?),
+        sequence: read_u32(segment, 4, Endian::Big)?,
+        flags: TcpFlags {
+            fin: flags & 0x01 != 0,
+            syn: flags & 0x02 != 0,
+            rst: flags & 0x04 != 0,
+            ack: flags & 0x10 != 0,
+        },
+        payload: segment.get(header_length..)?.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(source: u16, destination: u16, sequence: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = Vec::new();
+        segment.extend_from_slice(&source.to_be_bytes());
+        segment.extend_from_slice(&destination.to_be_bytes());
+        segment.extend_from_slice(&sequence.to_be_bytes());
+        segment.extend_from_slice(&[0; 4]);
+        segment.extend_from_slice(&[5 << 4, flags, 0xFF, 0xFF, 0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    fn ethernet_ipv4(segment: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x2A]); // a VLAN tag
+        frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&(20 + segment.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, PROTOCOL_TCP, 0, 0]);
+        frame.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 2]);
+        frame.extend_from_slice(segment);
+        // ethernet padding
+        frame.extend_from_slice(&[0; 4]);
+        frame
+    }
+
+    fn raw_ipv6(segment: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[PROTOCOL_TCP, 64]);
+        packet.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        packet.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        packet.extend_from_slice(segment);
+        packet
+    }
+
+    #[test]
+    fn pcap() {
+        let mut file = Vec::new();
+        file.extend_from_slice(&0xA1B2_C3D4u32.to_be_bytes());
+        file.extend_from_slice(&[0, 2, 0, 4]);
+        file.extend_from_slice(&[0; 12]);
+        file.extend_from_slice(&LINKTYPE_ETHERNET.to_be_bytes());
+
+        for (seconds, segment) in &[
+            (1u32, tcp(50000, 11811, 41, 0x02, b"")),
+            (2, tcp(50000, 11811, 42, 0x18, b"query")),
+        ] {
+            let frame = ethernet_ipv4(segment);
+            file.extend_from_slice(&seconds.to_be_bytes());
+            file.extend_from_slice(&500_000u32.to_be_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            file.extend_from_slice(&frame);
+        }
+
+        assert!(is_capture(&file));
+        let segments = read_capture(&file).unwrap();
+        assert_eq!(segments.len(), 2);
+
+        assert!(segments[0].flags.syn && !segments[0].flags.ack);
+        assert_eq!(segments[1].time, Duration::from_millis(2500));
+        assert_eq!(segments[1].source, "192.0.2.1:50000".parse().unwrap());
+        assert_eq!(segments[1].destination, "192.0.2.2:11811".parse().unwrap());
+        assert_eq!(segments[1].sequence, 42);
+        assert_eq!(segments[1].payload, b"query");
+    }
+
+    #[test]
+    fn pcapng() {
+        fn block(file: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+            let length = 12 + body.len() as u32;
+            file.extend_from_slice(&block_type.to_le_bytes());
+            file.extend_from_slice(&length.to_le_bytes());
+            file.extend_from_slice(body);
+            file.extend_from_slice(&length.to_le_bytes());
+        }
+
+        let mut file = Vec::new();
+        let mut header = PCAPNG_BYTE_ORDER.to_le_bytes().to_vec();
+        header.extend_from_slice(&[1, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        block(&mut file, PCAPNG_SECTION_HEADER, &header);
+
+        // millisecond timestamps
+        let mut interface = (LINKTYPE_RAW as u16).to_le_bytes().to_vec();
+        interface.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        interface.extend_from_slice(&[9, 0, 1, 0, 3, 0, 0, 0, 0, 0, 0, 0]);
+        block(&mut file, 1, &interface);
+
+        let packet = raw_ipv6(&tcp(134, 50000, 7, 0x10, b"data"));
+        let mut body = 0u32.to_le_bytes().to_vec();
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&1500u32.to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&packet);
+        body.resize((body.len() + 3) & !3, 0);
+        block(&mut file, 6, &body);
+
+        assert!(is_capture(&file));
+        let segments = read_capture(&file).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].time, Duration::from_millis(1500));
+        assert_eq!(segments[0].source, "[::1]:134".parse().unwrap());
+        assert!(segments[0].flags.ack);
+        assert_eq!(segments[0].payload, b"data");
+
+        assert!(read_capture(&file[..file.len() - 8]).is_err());
+        assert!(!is_capture(b"05 64 d2 04"));
+    }
+}
@@ -0,0 +1,773 @@
+use super::pcap::{read_capture, Segment};
+use super::*;
+use crate::centralex::{RemAck, RemConnect};
+use crate::server::text;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The port directory servers listen on.
+pub const DIRECTORY_PORT: u16 = 11811;
+
+/// The protocol spoken in a TCP session.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum SessionKind {
+    /// `Package<Server>`s
+    Directory,
+    /// the line based text protocol of the directory port
+    DirectoryText,
+    /// a subscriber registered at a centralex server, switching to the
+    /// client protocol once a call is forwarded
+    Centralex,
+    Client,
+}
+
+impl SessionKind {
+    pub const ALL: &'static [SessionKind] = &[
+        SessionKind::Directory,
+        SessionKind::DirectoryText,
+        SessionKind::Centralex,
+        SessionKind::Client,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SessionKind::Directory => "directory",
+            SessionKind::DirectoryText => "text",
+            SessionKind::Centralex => "centralex",
+            SessionKind::Client => "client",
+        }
+    }
+
+    /// Classify a session by the port of the server and the first package
+    /// the client sent.
+    pub fn classify(server_port: u16, request: &[u8]) -> Self {
+        let first_frame = request
+            .get(1)
+            .and_then(|length| request.get(..2 + *length as usize));
+        let decode = |class: PackageClass| first_frame.and_then(|frame| class.decode(frame).ok());
+
+        if let Some(Decoded::Centralex(package)) = decode(PackageClass::Centralex) {
+            if package.is::<RemConnect>() {
+                return SessionKind::Centralex;
+            }
+        }
+
+        if server_port == DIRECTORY_PORT || decode(PackageClass::Server).is_some() {
+            let protocol = request
+                .first()
+                .and_then(|byte| text::Protocol::from_first_byte(*byte));
+            if protocol == Some(text::Protocol::Text) {
+                SessionKind::DirectoryText
+            } else {
+                SessionKind::Directory
+            }
+        } else {
+            SessionKind::Client
+        }
+    }
+}
+
+impl std::str::FromStr for SessionKind {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        SessionKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.name().eq_ignore_ascii_case(string))
+            .ok_or_else(|| format!("unknown session kind {:?}", string))
+    }
+}
+
+impl std::fmt::Display for SessionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+/// What was sent in an `Event`.
+#[derive(Debug)]
+pub enum Content {
+    Frame(Frame),
+    /// a line of the text protocol, without its line terminator
+    Line(String),
+}
+
+impl Content {
+    /// Whether this is a subscriber accepting a call with `RemAck`, after
+    /// which both directions of its link to the centralex server carry the call.
+    pub fn accepts_call(&self) -> bool {
+        match self {
+            Content::Frame(Frame {
+                decoded: Ok(Decoded::Centralex(package)),
+                ..
+            }) => package.is::<RemAck>(),
+            _ => false,
+        }
+    }
+}
+
+/// A package or line sent in a session, timed by the segment completing it.
+#[derive(Debug)]
+pub struct Event {
+    /// since the unix epoch
+    pub time: Duration,
+    pub direction: Direction,
+    pub content: Content,
+}
+
+impl Event {
+    pub fn is_error(&self) -> bool {
+        match &self.content {
+            Content::Frame(frame) => frame.decoded.is_err(),
+            Content::Line(_) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let arrow = match self.direction {
+            Direction::ToServer => "-->",
+            Direction::ToClient => "<--",
+        };
+        write!(
+            f,
+            "{}.{:06}  {}  ",
+            self.time.as_secs(),
+            self.time.subsec_micros(),
+            arrow
+        )?;
+
+        match &self.content {
+            Content::Frame(frame) => write!(f, "{}", frame),
+            Content::Line(line) => write!(f, "{:?}", line),
+        }
+    }
+}
+
+/// A reassembled TCP session with its packages in the order they were sent.
+#[derive(Debug)]
+pub struct Session {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub kind: SessionKind,
+    /// whether the capture holds the session from its handshake on, without gaps
+    pub complete: bool,
+    pub events: Vec<Event>,
+}
+
+impl std::fmt::Display for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}  {}", self.client, self.server, self.kind)?;
+        if !self.complete {
+            write!(f, " (incomplete)")?;
+        }
+
+        for event in &self.events {
+            write!(f, "\n{}", event)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes what is sent in one direction of a session as it arrives.
+#[derive(Debug)]
+pub struct StreamDecoder {
+    kind: SessionKind,
+    direction: Direction,
+    class: PackageClass,
+    buffer: Vec<u8>,
+    /// the position of `buffer` in the stream
+    offset: usize,
+}
+
+impl StreamDecoder {
+    pub fn new(kind: SessionKind, direction: Direction) -> Self {
+        let class = match kind {
+            SessionKind::Directory | SessionKind::DirectoryText => PackageClass::Server,
+            SessionKind::Centralex => PackageClass::Centralex,
+            SessionKind::Client => PackageClass::Client,
+        };
+
+        StreamDecoder {
+            kind,
+            direction,
+            class,
+            buffer: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Add the next received bytes, returning the packages or lines they complete.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Content> {
+        self.buffer.extend_from_slice(bytes);
+        let buffer = std::mem::take(&mut self.buffer);
+
+        let mut contents = Vec::new();
+        let mut start = 0;
+        while let Some(length) = self.next_length(&buffer[start..]) {
+            contents.push(self.decode(&buffer[start..start + length]));
+            start += length;
+            self.offset += length;
+        }
+
+        self.buffer = buffer;
+        self.buffer.drain(..start);
+        contents
+    }
+
+    /// End the stream, returning the incomplete package or line left over.
+    pub fn finish(self) -> Option<Content> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        if self.kind == SessionKind::DirectoryText {
+            return Some(Content::Line(String::from_utf8_lossy(&self.buffer).into()));
+        }
+
+        let offset = self.offset;
+        dissect(&self.buffer, Some(self.class))
+            .into_iter()
+            .next()
+            .map(|mut frame| {
+                frame.offset += offset;
+                Content::Frame(frame)
+            })
+    }
+
+    /// Switch to the client protocol once the subscriber accepted a call, as
+    /// seen by `Content::accepts_call` in the other direction.
+    ///
+    /// The centralex server keeps sending centralex packages (like heartbeats)
+    /// after its `RemCall` until then.
+    pub fn call_accepted(&mut self) {
+        if self.class == PackageClass::Centralex {
+            self.class = PackageClass::Client;
+        }
+    }
+
+    /// The length of the package or line at the start of `bytes`, if it is complete.
+    fn next_length(&self, bytes: &[u8]) -> Option<usize> {
+        if self.kind == SessionKind::DirectoryText {
+            return bytes
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .map(|end| end + 1);
+        }
+
+        let length = 2 + *bytes.get(1)? as usize;
+        if bytes.len() >= length {
+            Some(length)
+        } else {
+            None
+        }
+    }
+
+    fn decode(&mut self, bytes: &[u8]) -> Content {
+        if self.kind == SessionKind::DirectoryText {
+            let line = String::from_utf8_lossy(bytes);
+            return Content::Line(line.trim_end_matches(&['\r', '\n'][..]).into());
+        }
+
+        let decoded = self.class.decode(bytes).map_err(|err| err.to_string());
+
+        let content = Content::Frame(Frame {
+            offset: self.offset,
+            package_type: bytes[0],
+            length: bytes[1],
+            bytes: bytes.to_vec(),
+            decoded,
+        });
+
+        // on a subscriber's link to a centralex server, the client protocol of
+        // a call follows the `RemAck`
+        if self.direction == Direction::ToServer && content.accepts_call() {
+            self.call_accepted();
+        }
+
+        content
+    }
+}
+
+/// The bytes sent in one direction of a connection.
+#[derive(Default)]
+struct Stream {
+    /// the sequence number of the first byte
+    start: Option<u32>,
+    data: Vec<u8>,
+    /// the length of `data` after each segment adding to it, with its time
+    arrivals: Vec<(usize, Duration)>,
+    /// segments after a gap, by their offset in the stream
+    pending: BTreeMap<u32, Vec<u8>>,
+}
+
+impl Stream {
+    fn push(&mut self, segment: &Segment) {
+        if segment.flags.syn {
+            self.start.get_or_insert(segment.sequence.wrapping_add(1));
+            return;
+        }
+        if segment.payload.is_empty() {
+            return;
+        }
+
+        let start = *self.start.get_or_insert(segment.sequence);
+        let offset = segment.sequence.wrapping_sub(start);
+        // retransmissions from before the capture picked up the stream
+        if offset > i32::MAX as u32 {
+            return;
+        }
+
+        let pending = self.pending.entry(offset).or_default();
+        if pending.len() < segment.payload.len() {
+            *pending = segment.payload.clone();
+        }
+
+        while let Some(offset) = self.pending.keys().next().copied() {
+            if offset as usize > self.data.len() {
+                break;
+            }
+
+            let payload = self.pending.remove(&offset).unwrap();
+            let overlap = self.data.len() - offset as usize;
+            if overlap < payload.len() {
+                self.data.extend_from_slice(&payload[overlap..]);
+                self.arrivals.push((self.data.len(), segment.time));
+            }
+        }
+    }
+
+    /// The data of each segment adding to the stream, with its time.
+    fn chunks(&self) -> impl Iterator<Item = (Duration, &[u8])> {
+        let starts = std::iter::once(0).chain(self.arrivals.iter().map(|(end, _)| *end));
+        starts
+            .zip(&self.arrivals)
+            .map(move |(start, (end, time))| (*time, &self.data[start..*end]))
+    }
+}
+
+struct Connection {
+    client: SocketAddr,
+    server: SocketAddr,
+    handshake: bool,
+    closed: bool,
+    to_server: Stream,
+    to_client: Stream,
+}
+
+impl Connection {
+    fn new(segment: &Segment) -> Self {
+        // without the handshake, whoever is seen first is as good a guess as any
+        let from_client = if segment.flags.syn {
+            !segment.flags.ack
+        } else {
+            segment.source.port() != DIRECTORY_PORT
+        };
+
+        let (client, server) = if from_client {
+            (segment.source, segment.destination)
+        } else {
+            (segment.destination, segment.source)
+        };
+
+        Connection {
+            client,
+            server,
+            handshake: segment.flags.syn,
+            closed: false,
+            to_server: Stream::default(),
+            to_client: Stream::default(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.to_server.data.is_empty() && self.to_client.data.is_empty()
+    }
+
+    fn push(&mut self, segment: &Segment) {
+        if segment.source == self.client {
+            self.to_server.push(segment);
+        } else {
+            self.to_client.push(segment);
+        }
+
+        if segment.flags.fin || segment.flags.rst {
+            self.closed = true;
+        }
+    }
+
+    fn into_session(self) -> Session {
+        let kind = SessionKind::classify(self.server.port(), &self.to_server.data);
+        let events = self.events(kind);
+
+        Session {
+            client: self.client,
+            server: self.server,
+            kind,
+            complete: self.handshake
+                && self.to_server.pending.is_empty()
+                && self.to_client.pending.is_empty(),
+            events,
+        }
+    }
+
+    /// Decode both directions in the order their data arrived, so that the
+    /// server's side of a centralex link knows when the call was accepted.
+    fn events(&self, kind: SessionKind) -> Vec<Event> {
+        let mut to_server = StreamDecoder::new(kind, Direction::ToServer);
+        let mut to_client = StreamDecoder::new(kind, Direction::ToClient);
+        let mut events = Vec::new();
+
+        let mut server_chunks = self.to_server.chunks().peekable();
+        let mut client_chunks = self.to_client.chunks().peekable();
+        loop {
+            // data sent to the server at the same time is taken to be first
+            let direction = match (server_chunks.peek(), client_chunks.peek()) {
+                (Some((server_time, _)), Some((client_time, _))) if client_time < server_time => {
+                    Direction::ToClient
+                }
+                (Some(_), _) => Direction::ToServer,
+                (None, Some(_)) => Direction::ToClient,
+                (None, None) => break,
+            };
+
+            let (time, contents) = match direction {
+                Direction::ToServer => {
+                    let (time, bytes) = server_chunks.next().unwrap();
+                    let contents = to_server.push(bytes);
+                    if contents.iter().any(Content::accepts_call) {
+                        to_client.call_accepted();
+                    }
+                    (time, contents)
+                }
+                Direction::ToClient => {
+                    let (time, bytes) = client_chunks.next().unwrap();
+                    (time, to_client.push(bytes))
+                }
+            };
+            events.extend(contents.into_iter().map(|content| Event {
+                time,
+                direction,
+                content,
+            }));
+        }
+
+        for (decoder, stream, direction) in [
+            (to_server, &self.to_server, Direction::ToServer),
+            (to_client, &self.to_client, Direction::ToClient),
+        ] {
+            if let (Some(content), Some((_, time))) = (decoder.finish(), stream.arrivals.last()) {
+                events.push(Event {
+                    time: *time,
+                    direction,
+                    content,
+                });
+            }
+        }
+        events.sort_by_key(|event| event.time);
+
+        events
+    }
+}
+
+/// Reassemble the TCP sessions in `segments`, in the order they started.
+///
+/// Sessions in which nothing was sent are left out.
+pub fn sessions(segments: &[Segment]) -> Vec<Session> {
+    let mut connections: Vec<Connection> = Vec::new();
+    let mut open = HashMap::new();
+
+    for segment in segments {
+        let key = if segment.source < segment.destination {
+            (segment.source, segment.destination)
+        } else {
+            (segment.destination, segment.source)
+        };
+
+        let reused = |connection: &Connection| {
+            segment.flags.syn && !segment.flags.ack && (connection.closed || !connection.is_empty())
+        };
+        let index = match open.get(&key) {
+            Some(&index) if !reused(&connections[index]) => index,
+            _ => {
+                connections.push(Connection::new(segment));
+                open.insert(key, connections.len() - 1);
+                connections.len() - 1
+            }
+        };
+
+        connections[index].push(segment);
+    }
+
+    connections
+        .into_iter()
+        .filter(|connection| !connection.is_empty())
+        .map(Connection::into_session)
+        .collect()
+}
+
+/// Read a pcap or pcapng file and reassemble the TCP sessions in it.
+pub fn dissect_capture(bytes: &[u8]) -> std::io::Result<Vec<Session>> {
+    Ok(sessions(&read_capture(bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::centralex::{Heartbeat, RemCall, RemConfirm, RemConnect};
+    use crate::client::DirectDial;
+    use crate::server::{PeerNotFound, PeerQuery};
+    use crate::Extension;
+
+    const CLIENT: &str = "192.0.2.1:50000";
+    const SERVER: &str = "192.0.2.2:11811";
+
+    fn segment(time: u64, to_server: bool, sequence: u32, payload: &[u8]) -> Segment {
+        let (source, destination) = if to_server {
+            (CLIENT, SERVER)
+        } else {
+            (SERVER, CLIENT)
+        };
+
+        Segment {
+            time: Duration::from_secs(time),
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+            sequence,
+            flags: pcap::TcpFlags::default(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn handshake() -> Vec<Segment> {
+        let mut syn = segment(0, true, 99, b"");
+        syn.flags.syn = true;
+        let mut syn_ack = segment(0, false, 499, b"");
+        syn_ack.flags.syn = true;
+        syn_ack.flags.ack = true;
+
+        vec![syn, syn_ack]
+    }
+
+    fn classes(session: &Session) -> Vec<(Direction, PackageClass)> {
+        session
+            .events
+            .iter()
+            .map(|event| match &event.content {
+                Content::Frame(frame) => (event.direction, frame.decoded.as_ref().unwrap().class()),
+                Content::Line(line) => panic!("unexpected line {:?}", line),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reassembles() {
+        let mut query = Vec::new();
+        Package::<Server>::new(PeerQuery {
+            number: 1234,
+            version: 1,
+        })
+        .serialize(&mut query)
+        .unwrap();
+        let mut reply = Vec::new();
+        Package::<Server>::new(PeerNotFound {})
+            .serialize(&mut reply)
+            .unwrap();
+
+        let mut segments = handshake();
+        // out of order, with a retransmission overlapping both parts
+        segments.push(segment(2, true, 103, &query[3..]));
+        segments.push(segment(1, true, 100, &query[..3]));
+        segments.push(segment(3, true, 101, &query[1..5]));
+        segments.push(segment(4, false, 500, &reply));
+
+        let sessions = sessions(&segments);
+        assert_eq!(sessions.len(), 1);
+
+        let session = &sessions[0];
+        assert_eq!(session.client, CLIENT.parse().unwrap());
+        assert_eq!(session.kind, SessionKind::Directory);
+        assert!(session.complete);
+        assert_eq!(
+            classes(session),
+            vec![
+                (Direction::ToServer, PackageClass::Server),
+                (Direction::ToClient, PackageClass::Server)
+            ]
+        );
+        // the query is complete once the segment filling the gap arrived
+        assert_eq!(session.events[0].time, Duration::from_secs(1));
+        assert_eq!(
+            session.events[1].to_string(),
+            "4.000000  <--  000000  type 0x04  length   0  server: Package<Server>(PeerNotFound)"
+        );
+    }
+
+    #[test]
+    fn centralex() {
+        let mut to_server = Vec::new();
+        Package::<Centralex>::new(RemConnect {
+            number: 1234,
+            pin: 4711,
+        })
+        .serialize(&mut to_server)
+        .unwrap();
+        let mut to_client = Vec::new();
+        Package::<Centralex>::new(RemConfirm {})
+            .serialize(&mut to_client)
+            .unwrap();
+        Package::<Centralex>::new(RemCall::from(std::net::IpAddr::from([192, 0, 2, 3])))
+            .serialize(&mut to_client)
+            .unwrap();
+        // the subscriber has yet to accept the call
+        Package::<Centralex>::new(Heartbeat {})
+            .serialize(&mut to_client)
+            .unwrap();
+        let remote = to_client.len() as u32;
+        let remote_length = to_server.len() as u32;
+
+        Package::<Centralex>::new(RemAck {})
+            .serialize(&mut to_server)
+            .unwrap();
+        let direct_dial = Package::<Client>::new(DirectDial {
+            extension: Extension::NONE,
+        });
+        let mut call = Vec::new();
+        direct_dial.serialize(&mut call).unwrap();
+
+        let mut segments = handshake();
+        segments.push(segment(1, true, 100, &to_server[..remote_length as usize]));
+        segments.push(segment(2, false, 500, &to_client));
+        segments.push(segment(
+            3,
+            true,
+            100 + remote_length,
+            &to_server[remote_length as usize..],
+        ));
+        segments.push(segment(4, false, 500 + remote, &call));
+        // the server port is no hint for centralex
+        for segment in &mut segments {
+            for address in &mut [&mut segment.source, &mut segment.destination] {
+                if address.port() == DIRECTORY_PORT {
+                    address.set_port(49491);
+                }
+            }
+        }
+
+        let sessions = sessions(&segments);
+        assert_eq!(sessions[0].kind, SessionKind::Centralex);
+        assert_eq!(
+            classes(&sessions[0]),
+            vec![
+                (Direction::ToServer, PackageClass::Centralex),
+                (Direction::ToClient, PackageClass::Centralex),
+                (Direction::ToClient, PackageClass::Centralex),
+                (Direction::ToClient, PackageClass::Centralex),
+                (Direction::ToServer, PackageClass::Centralex),
+                (Direction::ToClient, PackageClass::Client),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_incrementally() {
+        let mut bytes = Vec::new();
+        Package::<Centralex>::new(RemConnect {
+            number: 1234,
+            pin: 4711,
+        })
+        .serialize(&mut bytes)
+        .unwrap();
+        Package::<Centralex>::new(RemAck {})
+            .serialize(&mut bytes)
+            .unwrap();
+        Package::<Client>::new(DirectDial {
+            extension: Extension::NONE,
+        })
+        .serialize(&mut bytes)
+        .unwrap();
+        // a package cut short
+        bytes.extend_from_slice(&[0x04, 0x05, b'o']);
+
+        let mut decoder = StreamDecoder::new(SessionKind::Centralex, Direction::ToServer);
+        let mut frames = Vec::new();
+        for byte in &bytes {
+            frames.extend(decoder.push(&[*byte]));
+        }
+        frames.extend(decoder.finish());
+
+        let frames: Vec<Frame> = frames
+            .into_iter()
+            .map(|content| match content {
+                Content::Frame(frame) => frame,
+                Content::Line(line) => panic!("unexpected line {:?}", line),
+            })
+            .collect();
+        assert_eq!(
+            frames.iter().map(|frame| frame.offset).collect::<Vec<_>>(),
+            vec![0, 8, 10, 13]
+        );
+        assert_eq!(
+            frames[..3]
+                .iter()
+                .map(|frame| frame.decoded.as_ref().unwrap().class())
+                .collect::<Vec<_>>(),
+            vec![
+                PackageClass::Centralex,
+                PackageClass::Centralex,
+                PackageClass::Client
+            ]
+        );
+        assert!(frames[3].is_truncated());
+    }
+
+    #[test]
+    fn text() {
+        let mut segments = handshake();
+        segments.push(segment(1, true, 100, b"q1234\r\n"));
+        segments.push(segment(2, false, 500, b"fail\r\n1234\r\n"));
+        segments.push(segment(3, false, 512, b"unknown\r\n+++\r\n"));
+
+        let session = &sessions(&segments)[0];
+        assert_eq!(session.kind, SessionKind::DirectoryText);
+
+        let lines: Vec<&str> = session
+            .events
+            .iter()
+            .map(|event| match &event.content {
+                Content::Line(line) => line.as_str(),
+                Content::Frame(frame) => panic!("unexpected frame {}", frame),
+            })
+            .collect();
+        assert_eq!(lines, vec!["q1234", "fail", "1234", "unknown", "+++"]);
+    }
+
+    #[test]
+    fn without_handshake() {
+        let mut call = Vec::new();
+        Package::<Client>::new(DirectDial {
+            extension: Extension::NONE,
+        })
+        .serialize(&mut call)
+        .unwrap();
+
+        let mut segment = segment(1, true, 7, &call);
+        segment.destination.set_port(134);
+
+        let sessions = sessions(&[segment]);
+        assert_eq!(sessions[0].kind, SessionKind::Client);
+        assert_eq!(sessions[0].client, CLIENT.parse().unwrap());
+        assert!(!sessions[0].complete);
+        assert!(!sessions[0].events[0].is_error());
+    }
+}
@@ -0,0 +1,223 @@
+use crate::io;
+use crate::wire::{Decode, Encode};
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::convert::TryFrom;
+use core::fmt;
+
+/// The extension of a subscriber, as used by `PeerReply` and `DirectDial`.
+///
+/// On the wire extensions are encoded in a single byte:
+///
+/// | wire value | extension     |
+/// |------------|---------------|
+/// | 0          | none (`-`)    |
+/// | 1 - 99     | `01` - `99`   |
+/// | 100        | `00`          |
+/// | 101 - 109  | `1` - `9`     |
+/// | 110        | `0`           |
+///
+/// All other values are invalid. They are still kept by `from_wire`, so that
+/// entries with invalid extensions survive a round trip unchanged.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone, Default)]
+pub struct Extension(u8);
+
+impl Extension {
+    pub const NONE: Extension = Extension(0);
+
+    /// The extension encoded by `byte`, even if `byte` is not a valid extension.
+    pub fn from_wire(byte: u8) -> Self {
+        Extension(byte)
+    }
+
+    pub fn to_wire(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_valid(self) -> bool {
+        self.0 <= 110
+    }
+
+    pub fn is_none(self) -> bool {
+        self == Self::NONE
+    }
+
+    /// The textual representation of this extension, or the wire value if it is invalid.
+    pub fn as_string(self) -> Result<String, u8> {
+        Ok(match self.0 {
+            0 => "-".into(),
+            100 => "00".into(),
+            110 => "0".into(),
+            x if x < 100 => format!("{:02}", x),
+            x if x > 100 && x < 110 => (x - 100).to_string(),
+            x => return Err(x),
+        })
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ExtensionError {
+    /// the wire value does not encode an extension
+    InvalidWireValue(u8),
+    /// the string is not `-`, empty, or one or two digits
+    InvalidString(String),
+}
+
+impl fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtensionError::InvalidWireValue(byte) => {
+                write!(f, "{} is not a valid extension", byte)
+            }
+            ExtensionError::InvalidString(string) => {
+                write!(f, "{:?} is not a valid extension", string)
+            }
+        }
+    }
+}
+
+impl core::error::Error for ExtensionError {}
+
+impl TryFrom<u8> for Extension {
+    type Error = ExtensionError;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        let extension = Extension(byte);
+
+        if extension.is_valid() {
+            Ok(extension)
+        } else {
+            Err(ExtensionError::InvalidWireValue(byte))
+        }
+    }
+}
+
+impl From<Extension> for u8 {
+    fn from(extension: Extension) -> u8 {
+        extension.0
+    }
+}
+
+impl core::str::FromStr for Extension {
+    type Err = ExtensionError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if string == "-" {
+            return Ok(Self::NONE);
+        }
+
+        // invalid extensions as displayed
+        if let Some(byte) = string.strip_prefix('?') {
+            return byte
+                .parse()
+                .map(Extension::from_wire)
+                .map_err(|_| ExtensionError::InvalidString(string.into()));
+        }
+
+        let digits = string.as_bytes();
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return Err(ExtensionError::InvalidString(string.into()));
+        }
+
+        Ok(Extension(match digits {
+            b"" => 0,
+            [b'0'] => 110,
+            [b'0', b'0'] => 100,
+            [digit] => 100 + (digit - b'0'),
+            [tens, ones] => (tens - b'0') * 10 + (ones - b'0'),
+            _ => return Err(ExtensionError::InvalidString(string.into())),
+        }))
+    }
+}
+
+/// Invalid extensions are displayed as `?` followed by their wire value.
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.as_string() {
+            Ok(string) => write!(f, "{}", string),
+            Err(byte) => write!(f, "?{}", byte),
+        }
+    }
+}
+
+impl Encode for Extension {
+    fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        self.0.encode(writer)
+    }
+}
+
+impl Decode for Extension {
+    fn decode(reader: &mut impl io::Read) -> io::Result<Self> {
+        Ok(Extension::from_wire(u8::decode(reader)?))
+    }
+}
+
+#[cfg(feature = "serde_serialize")]
+impl serde::Serialize for Extension {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u8(self.0)
+    }
+}
+
+#[cfg(feature = "serde_deserialize")]
+impl<'de> serde::Deserialize<'de> for Extension {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // like `Decode`, keep invalid extensions so that entries survive a round trip
+        u8::deserialize(deserializer).map(Extension::from_wire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for byte in 0..=110 {
+            let extension = Extension::try_from(byte).unwrap();
+            assert_eq!(extension.to_string().parse(), Ok(extension));
+        }
+
+        assert_eq!(
+            Extension::try_from(111),
+            Err(ExtensionError::InvalidWireValue(111))
+        );
+        assert_eq!(Extension::from_wire(111).to_string(), "?111");
+        assert_eq!("?111".parse(), Ok(Extension::from_wire(111)));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("".parse(), Ok(Extension::NONE));
+        assert_eq!("-".parse(), Ok(Extension::NONE));
+        assert_eq!("0".parse(), Ok(Extension::from_wire(110)));
+        assert_eq!("00".parse(), Ok(Extension::from_wire(100)));
+        assert_eq!("7".parse(), Ok(Extension::from_wire(107)));
+        assert_eq!("07".parse(), Ok(Extension::from_wire(7)));
+        assert_eq!("42".parse(), Ok(Extension::from_wire(42)));
+        assert!("123".parse::<Extension>().is_err());
+        assert!("a".parse::<Extension>().is_err());
+        assert!("?".parse::<Extension>().is_err());
+        assert!("?256".parse::<Extension>().is_err());
+    }
+
+    #[cfg(all(
+        feature = "serde_serialize",
+        feature = "serde_deserialize",
+        feature = "serde_json"
+    ))]
+    #[test]
+    fn serde_round_trip() {
+        for byte in &[0, 105, 111, 255] {
+            let extension = Extension::from_wire(*byte);
+            let json = serde_json::to_string(&extension).unwrap();
+            assert_eq!(serde_json::from_str::<Extension>(&json).unwrap(), extension);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! The minimal reading and writing the package codec needs.
+//!
+//! With the `std` feature the error types are those of `std::io` and every
+//! `std::io::Read` or `std::io::Write` can be used directly. Without it,
+//! packages are read from byte slices and written to `Vec<u8>`s, or to
+//! anything else implementing `Read` and `Write` (like the UART of a
+//! microcontroller).
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+pub use self::no_std::{Error, ErrorKind, Result};
+
+/// A source of bytes.
+pub trait Read {
+    /// Fill all of `buffer`, failing with `ErrorKind::UnexpectedEof` if there
+    /// are not enough bytes left.
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()>;
+}
+
+/// A sink for bytes.
+pub trait Write {
+    /// Write all of `buffer`.
+    fn write_all(&mut self, buffer: &[u8]) -> Result<()>;
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> Read for R {
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        std::io::Read::read_exact(self, buffer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> Write for W {
+    fn write_all(&mut self, buffer: &[u8]) -> Result<()> {
+        std::io::Write::write_all(self, buffer)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use super::{Read, Write};
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::fmt;
+
+    /// The kinds of errors the codec produces, named like those of `std::io`.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        InvalidInput,
+        InvalidData,
+        UnexpectedEof,
+        Other,
+    }
+
+    /// An error while reading or writing, like `std::io::Error`.
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: String,
+    }
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    impl Error {
+        pub fn new(kind: ErrorKind, error: impl fmt::Display) -> Self {
+            Error {
+                kind,
+                message: error.to_string(),
+            }
+        }
+
+        pub fn other(error: impl fmt::Display) -> Self {
+            Self::new(ErrorKind::Other, error)
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Error {
+                kind,
+                message: String::new(),
+            }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            if self.message.is_empty() {
+                write!(f, "{:?}", self.kind)
+            } else {
+                write!(f, "{}", self.message)
+            }
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    impl Read for &[u8] {
+        fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+            if buffer.len() > self.len() {
+                *self = &self[self.len()..];
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ));
+            }
+
+            let (head, tail) = self.split_at(buffer.len());
+            buffer.copy_from_slice(head);
+            *self = tail;
+            Ok(())
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+            (**self).read_exact(buffer)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write_all(&mut self, buffer: &[u8]) -> Result<()> {
+            self.extend_from_slice(buffer);
+            Ok(())
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write_all(&mut self, buffer: &[u8]) -> Result<()> {
+            (**self).write_all(buffer)
+        }
+    }
+}
@@ -1,15 +1,31 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
 use core::any::Any;
 
+#[macro_use]
+pub mod wire;
+
+pub mod io;
+
 pub trait Class: PartialEq + Copy {
     const NAME: &'static str;
 }
 
-#[derive(Debug, Copy, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[cfg(any(feature = "server", feature = "client", feature = "centralex"))]
+#[derive(Debug, Copy, Clone)]
 struct Header {
     package_type: u8,
     package_length: u8,
 }
 
+#[cfg(any(feature = "server", feature = "client", feature = "centralex"))]
+wire_struct! {
+    Header { package_type, package_length }
+}
+
 #[cfg(all(feature = "serde_serialize", feature = "serde_deserialize"))]
 pub trait SerdeBounds: serde::Serialize + serde::Deserialize<'static> {}
 #[cfg(all(feature = "serde_serialize", not(feature = "serde_deserialize")))]
@@ -27,9 +43,9 @@ where
         + Any
         + Sync
         + Send
-        + std::fmt::Debug
-        + std::cmp::Eq
-        + std::cmp::PartialEq
+        + core::fmt::Debug
+        + core::cmp::Eq
+        + core::cmp::PartialEq
         + Clone
         + 'static
         + SerdeBounds,
@@ -45,8 +61,8 @@ where
     fn package_type(&self) -> Self::Class {
         Self::VARIANT
     }
-    fn serialize(&self, writer: &mut impl std::io::Write) -> std::io::Result<()>;
-    fn deserialize(reader: &mut impl std::io::Read) -> std::io::Result<Option<Self>>;
+    fn serialize(&self, writer: &mut impl io::Write) -> io::Result<()>;
+    fn deserialize(reader: &mut impl io::Read) -> io::Result<Option<Self>>;
 }
 pub struct Package<T> {
     inner: Box<dyn Any + Send + Sync>,
@@ -74,7 +90,7 @@ impl<C: Class> Package<C> {
 
     pub fn downcast<P: PackageBody<Class = C>>(self) -> Option<Box<P>> {
         if self.package_type == P::VARIANT {
-            Some(std::boxed::Box::<(dyn Any + Send)>::downcast::<P>(self.inner).unwrap())
+            Some(Box::<dyn Any + Send>::downcast::<P>(self.inner).unwrap())
         } else {
             None
         }
@@ -108,21 +124,24 @@ impl<C: Class> Package<C> {
     }
 }
 
+#[cfg(any(feature = "server", feature = "client", feature = "centralex"))]
 #[derive(Copy, Clone, Debug)]
 struct NotAPackage;
-impl std::error::Error for NotAPackage {}
-impl std::fmt::Display for NotAPackage {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+#[cfg(any(feature = "server", feature = "client", feature = "centralex"))]
+impl core::error::Error for NotAPackage {}
+#[cfg(any(feature = "server", feature = "client", feature = "centralex"))]
+impl core::fmt::Display for NotAPackage {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
+#[cfg(any(feature = "server", feature = "client", feature = "centralex"))]
 macro_rules! package_class {
     ($class:ident ( $name:literal ), $($package_name:ident = $discriminant:literal,)*) => {
         use crate::{Package, PackageBody, Header, NotAPackage, Class};
-        use binserde::{Deserialize};
 
-        use std::convert::{TryInto, TryFrom};
+        use core::convert::{TryInto, TryFrom};
 
         #[repr(u8)]
         #[derive(Copy, Clone, Eq, PartialEq)]
@@ -135,33 +154,33 @@ macro_rules! package_class {
         }
 
         impl Package<$class> {
-            pub fn serialize(&self, writer: &mut impl std::io::Write) -> std::io::Result<()> {
+            pub fn serialize(&self, writer: &mut impl crate::io::Write) -> crate::io::Result<()> {
                 $(
                     if let Some(pkg) = self.downcast_ref::<$package_name>() {
                         return pkg.serialize(writer);
                     }
                 )*
 
-                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, NotAPackage));
+                return Err(crate::io::Error::new(crate::io::ErrorKind::InvalidInput, NotAPackage));
             }
 
-            pub fn deserialize(reader: &mut impl std::io::Read) -> std::io::Result<Self> {
-                let header = Header::deserialize_ne(reader)?;
-                let mut buffer = vec![0; header.package_length as usize];
+            pub fn deserialize(reader: &mut impl crate::io::Read) -> crate::io::Result<Self> {
+                let header = <Header as crate::wire::Decode>::decode(reader)?;
+                let mut buffer = alloc::vec![0; header.package_length as usize];
 
                 reader.read_exact(&mut buffer)?;
 
-                match header.package_type.try_into().map_err(|_err| std::io::Error::new(std::io::ErrorKind::InvalidInput, NotAPackage))? {
+                match header.package_type.try_into().map_err(|_err| crate::io::Error::new(crate::io::ErrorKind::InvalidInput, NotAPackage))? {
                     $($class::$package_name => {
-                        $package_name::deserialize_le(&mut std::io::Cursor::new(buffer)).map(Package::new)
+                        <$package_name as crate::wire::Decode>::decode(&mut &buffer[..]).map(Package::new)
                     })*
                 }
             }
         }
 
-        impl Into<u8> for $class {
-            fn into(self) -> u8 {
-                self as u8
+        impl From<$class> for u8 {
+            fn from(class: $class) -> u8 {
+                class as u8
             }
         }
 
@@ -176,14 +195,14 @@ macro_rules! package_class {
         }
 
         $(
-            impl Into<Package<$class>> for $package_name {
-                fn into(self) -> Package<$class> {
-                    self.to_package()
+            impl From<$package_name> for Package<$class> {
+                fn from(pkg: $package_name) -> Package<$class> {
+                    pkg.to_package()
                 }
             }
 
-            impl From<Box<$package_name>> for Package<$class> {
-                fn from(pkg: Box<$package_name>) -> Package<$class> {
+            impl From<alloc::boxed::Box<$package_name>> for Package<$class> {
+                fn from(pkg: alloc::boxed::Box<$package_name>) -> Package<$class> {
                     Package::<$class>::from_box(pkg)
                 }
             }
@@ -197,9 +216,21 @@ macro_rules! package_class {
         )*
 
 
-        impl std::fmt::Debug for Package<$class> {
-            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-                let name = format!("Package<{}>", $class::NAME);
+        impl PartialEq for Package<$class> {
+            fn eq(&self, other: &Self) -> bool {
+                match self.package_type() {
+                    $($class::$package_name => {
+                        self.downcast_ref::<$package_name>() == other.downcast_ref::<$package_name>()
+                    })*
+                }
+            }
+        }
+
+        impl Eq for Package<$class> {}
+
+        impl core::fmt::Debug for Package<$class> {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                let name = alloc::format!("Package<{}>", $class::NAME);
                 let mut struct_debugger = f.debug_tuple(&name);
                 match self.package_type() {
                     $($class::$package_name => {
@@ -217,30 +248,29 @@ macro_rules! package_class {
             impl PackageBody for $package_name {
                 type Class = $class;
                 const VARIANT: $class = $class::$package_name;
-                fn serialize(&self, writer: &mut impl std::io::Write) -> std::io::Result<()> {
-                    use binserde::Serialize;
-
-                    let mut buffer = Vec::new();
-                    self.serialize_le(&mut buffer)?;
+                fn serialize(&self, writer: &mut impl crate::io::Write) -> crate::io::Result<()> {
+                    let mut buffer = alloc::vec::Vec::new();
+                    crate::wire::Encode::encode(self, &mut buffer)?;
 
-                    Header {
+                    let header = Header {
                         package_type: self.package_type() as u8,
                         package_length: buffer.len() as u8,
-                    }.serialize_le(writer)?;
+                    };
+                    crate::wire::Encode::encode(&header, writer)?;
 
                     writer.write_all(&buffer)?;
 
                     Ok(())
                 }
-                fn deserialize(reader: &mut impl std::io::Read) -> std::io::Result<Option<Self>> {
-                    let header = Header::deserialize_le(reader)?;
-                    let mut buffer = vec![0; header.package_length as usize];
+                fn deserialize(reader: &mut impl crate::io::Read) -> crate::io::Result<Option<Self>> {
+                    let header = <Header as crate::wire::Decode>::decode(reader)?;
+                    let mut buffer = alloc::vec![0; header.package_length as usize];
 
                     reader.read_exact(&mut buffer)?;
 
-                    let package_type: $class = header.package_type.try_into().map_err(|_err| std::io::Error::new(std::io::ErrorKind::InvalidInput, NotAPackage))?;
+                    let package_type: $class = header.package_type.try_into().map_err(|_err| crate::io::Error::new(crate::io::ErrorKind::InvalidInput, NotAPackage))?;
                     if package_type == $class::$package_name {
-                        $package_name::deserialize_le(&mut std::io::Cursor::new(buffer)).map(Some)
+                        <$package_name as crate::wire::Decode>::decode(&mut &buffer[..]).map(Some)
                     } else {
                         Ok(None)
                     }
@@ -250,6 +280,11 @@ macro_rules! package_class {
     };
 }
 
+#[cfg(any(feature = "server", feature = "client"))]
+mod extension;
+#[cfg(any(feature = "server", feature = "client"))]
+pub use extension::*;
+
 #[cfg(feature = "client")]
 pub mod client;
 
@@ -258,3 +293,15 @@ pub mod server;
 
 #[cfg(feature = "centralex")]
 pub mod centralex;
+
+#[cfg(all(
+    feature = "std",
+    any(feature = "server", feature = "client", feature = "centralex")
+))]
+pub mod dissect;
+
+#[cfg(all(feature = "server", feature = "centralex", feature = "client"))]
+pub mod proxy;
+
+#[cfg(feature = "proptest")]
+pub mod strategies;
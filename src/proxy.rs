@@ -0,0 +1,387 @@
+//! A man-in-the-middle proxy for debugging, forwarding connections to a
+//! directory server, centralex server or subscriber and logging every package
+//! passing through.
+
+use crate::dissect::{Content, Direction, Event, SessionKind, StreamDecoder};
+use crate::server::text;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Something that happened on a proxied connection.
+#[derive(Debug)]
+pub enum Record {
+    /// the connection to the upstream was established
+    Connected(SocketAddr),
+    /// the connection was found to speak this protocol
+    Classified(SessionKind),
+    Event(Event),
+    /// the sending side closed its direction of the connection
+    Closed(Direction),
+    Failed(std::io::Error),
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Record::Connected(upstream) => write!(f, "connected to {}", upstream),
+            Record::Classified(kind) => write!(f, "speaking {}", kind),
+            Record::Event(event) => write!(f, "{}", event),
+            Record::Closed(Direction::ToServer) => write!(f, "client closed the connection"),
+            Record::Closed(Direction::ToClient) => write!(f, "server closed the connection"),
+            Record::Failed(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+/// Receives the `Record`s of all connections, by the address of their client.
+pub trait Logger: Send + Sync {
+    fn log(&self, client: SocketAddr, record: Record);
+}
+
+impl<F: Fn(SocketAddr, Record) + Send + Sync> Logger for F {
+    fn log(&self, client: SocketAddr, record: Record) {
+        self(client, record)
+    }
+}
+
+/// Forwards connections to an upstream, logging what is sent either way.
+///
+/// Unless set with `with_kind`, the protocol of each connection is classified
+/// like in packet captures, by the first package the client sends.
+pub struct Proxy<L> {
+    upstream: Vec<SocketAddr>,
+    kind: Option<SessionKind>,
+    logger: L,
+}
+
+impl<L: Logger> Proxy<L> {
+    pub fn new(upstream: impl ToSocketAddrs, logger: L) -> std::io::Result<Self> {
+        Ok(Proxy {
+            upstream: upstream.to_socket_addrs()?.collect(),
+            kind: None,
+            logger,
+        })
+    }
+
+    pub fn with_kind(mut self, kind: SessionKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn logger(&self) -> &L {
+        &self.logger
+    }
+
+    /// Connect `client` to the upstream and forward until both sides are done.
+    pub fn relay(&self, client: TcpStream) -> std::io::Result<()> {
+        let peer = client.peer_addr()?;
+        let server = TcpStream::connect(&self.upstream[..])?;
+        let server_port = server.peer_addr()?.port();
+        self.logger
+            .log(peer, Record::Connected(server.peer_addr()?));
+
+        let link = Link {
+            kind: Mutex::new(self.kind),
+            call_accepted: AtomicBool::new(false),
+        };
+        let (client_reader, server_writer) = (client.try_clone()?, server.try_clone()?);
+
+        std::thread::scope(|scope| {
+            let forward = scope.spawn(|| {
+                self.forward(
+                    peer,
+                    Direction::ToServer,
+                    client_reader,
+                    server_writer,
+                    &link,
+                    server_port,
+                )
+            });
+            let result = self.forward(
+                peer,
+                Direction::ToClient,
+                server,
+                client,
+                &link,
+                server_port,
+            );
+
+            forward
+                .join()
+                .map_err(|_| std::io::Error::other("proxy thread panicked"))??;
+            result
+        })
+    }
+
+    /// Accept connections on `listener`, relaying each on its own thread.
+    pub fn listen(self: Arc<Self>, listener: TcpListener) -> std::io::Result<()>
+    where
+        L: 'static,
+    {
+        loop {
+            let (stream, peer) = match listener.accept() {
+                Ok(connection) => connection,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            let proxy = self.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = proxy.relay(stream) {
+                    proxy.logger.log(peer, Record::Failed(err));
+                }
+            });
+        }
+    }
+
+    fn forward(
+        &self,
+        peer: SocketAddr,
+        direction: Direction,
+        mut reader: TcpStream,
+        mut writer: TcpStream,
+        link: &Link,
+        server_port: u16,
+    ) -> std::io::Result<()> {
+        let log = |content| {
+            let event = Event {
+                time: now(),
+                direction,
+                content,
+            };
+            self.logger.log(peer, Record::Event(event));
+        };
+
+        // bytes received before the protocol is known
+        let mut received = Vec::new();
+        let mut decoder = None;
+
+        let mut buffer = [0; 4096];
+        let result = loop {
+            let length = match reader.read(&mut buffer) {
+                Ok(length) => length,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => break Err(err),
+            };
+            let bytes = &buffer[..length];
+
+            if decoder.is_none() {
+                received.extend_from_slice(bytes);
+                let classified = self.classify(
+                    peer,
+                    direction,
+                    &received,
+                    length == 0,
+                    &link.kind,
+                    server_port,
+                );
+                if let Some(kind) = classified {
+                    decoder = Some(StreamDecoder::new(kind, direction));
+                }
+            }
+
+            if let Some(decoder) = &mut decoder {
+                let bytes = if received.is_empty() {
+                    bytes
+                } else {
+                    &received[..]
+                };
+                // the subscriber's `RemAck` is logged before it is forwarded,
+                // so the server's side of the call comes after it
+                if direction == Direction::ToClient && link.call_accepted.load(Ordering::SeqCst) {
+                    decoder.call_accepted();
+                }
+                let contents = decoder.push(bytes);
+                if contents.iter().any(Content::accepts_call) {
+                    link.call_accepted.store(true, Ordering::SeqCst);
+                }
+                contents.into_iter().for_each(log);
+                received.clear();
+            }
+
+            // logging first keeps the records in the order things happened
+            if let Err(err) = writer.write_all(bytes) {
+                break Err(err);
+            }
+            if length == 0 {
+                break Ok(());
+            }
+        };
+
+        if let Some(content) = decoder.and_then(StreamDecoder::finish) {
+            log(content);
+        }
+
+        let _ = writer.shutdown(Shutdown::Write);
+        self.logger.log(peer, Record::Closed(direction));
+        result
+    }
+
+    /// The protocol of the connection, once enough has been received to tell.
+    ///
+    /// If the server speaks first, the protocol is taken from its port.
+    fn classify(
+        &self,
+        peer: SocketAddr,
+        direction: Direction,
+        received: &[u8],
+        closed: bool,
+        kind: &Mutex<Option<SessionKind>>,
+        server_port: u16,
+    ) -> Option<SessionKind> {
+        let mut kind = kind.lock().unwrap_or_else(|err| err.into_inner());
+        if kind.is_some() {
+            return *kind;
+        }
+
+        // binary packages may contain newlines, so only text queries end at one
+        let protocol = received
+            .first()
+            .and_then(|byte| text::Protocol::from_first_byte(*byte));
+        let complete = closed
+            || match received {
+                _ if protocol == Some(text::Protocol::Text) => received.contains(&b'\n'),
+                [_, length, ..] => received.len() >= 2 + *length as usize,
+                _ => false,
+            };
+        if !complete {
+            return None;
+        }
+
+        let classified = match direction {
+            Direction::ToServer => SessionKind::classify(server_port, received),
+            Direction::ToClient => SessionKind::classify(server_port, &[]),
+        };
+        self.logger.log(peer, Record::Classified(classified));
+
+        *kind = Some(classified);
+        *kind
+    }
+}
+
+/// The current time, since the unix epoch.
+/// What the two directions of a relayed connection share.
+struct Link {
+    kind: Mutex<Option<SessionKind>>,
+    /// whether the subscriber accepted a call on a centralex link
+    call_accepted: AtomicBool,
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dissect::PackageClass;
+    use crate::server::{DirectoryClient, DirectoryServer, MemoryStore};
+    use crate::PackageBody;
+
+    #[test]
+    fn classifies_complete_packages() {
+        let proxy = Proxy::new("127.0.0.1:1", |_, _| {}).unwrap();
+        let peer = ([192, 0, 2, 1], 1).into();
+        let kind = Mutex::new(None);
+        let classify = |received: &[u8], closed| {
+            proxy.classify(peer, Direction::ToServer, received, closed, &kind, 134)
+        };
+
+        // a RemConnect for number 10, whose first bytes contain a newline
+        let mut request = Vec::new();
+        crate::centralex::RemConnect { number: 10, pin: 0 }
+            .serialize(&mut request)
+            .unwrap();
+        assert_eq!(classify(&request[..3], false), None);
+        assert_eq!(classify(&request, false), Some(SessionKind::Centralex));
+
+        let kind = Mutex::new(None);
+        let classify = |received: &[u8], closed| {
+            proxy.classify(
+                peer,
+                Direction::ToServer,
+                received,
+                closed,
+                &kind,
+                crate::dissect::DIRECTORY_PORT,
+            )
+        };
+        assert_eq!(classify(b"q12", false), None);
+        assert_eq!(
+            classify(b"q12\r\n", false),
+            Some(SessionKind::DirectoryText)
+        );
+    }
+
+    #[test]
+    fn logs_directory_queries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let directory = listener.local_addr().unwrap();
+        let server = Arc::new(DirectoryServer::new(MemoryStore::new(), 0));
+        std::thread::spawn(move || crate::server::listen(listener, server));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = Mutex::new(sender);
+        let proxy = Proxy::new(directory, move |_, record| {
+            let _ = sender.lock().unwrap().send(record);
+        })
+        .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || Arc::new(proxy).listen(listener));
+
+        let mut client = DirectoryClient::connect(address).unwrap();
+        assert_eq!(client.query(1234).unwrap(), None);
+        drop(client);
+
+        let mut records = Vec::new();
+        for record in receiver.iter() {
+            let done = matches!(record, Record::Closed(Direction::ToClient));
+            records.push(record);
+            if done {
+                break;
+            }
+        }
+
+        assert!(matches!(records[0], Record::Connected(upstream) if upstream == directory));
+        assert!(matches!(
+            records[1],
+            Record::Classified(SessionKind::Directory)
+        ));
+
+        let packages: Vec<(Direction, String)> = records
+            .iter()
+            .filter_map(|record| match record {
+                Record::Event(Event {
+                    direction,
+                    content: Content::Frame(frame),
+                    ..
+                }) => {
+                    let decoded = frame.decoded.as_ref().unwrap();
+                    assert_eq!(decoded.class(), PackageClass::Server);
+                    Some((*direction, format!("{:?}", decoded)))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            packages,
+            vec![
+                (
+                    Direction::ToServer,
+                    String::from("Package<Server>(PeerQuery { number: 1234, version: 1 })")
+                ),
+                (
+                    Direction::ToClient,
+                    String::from("Package<Server>(PeerNotFound)")
+                ),
+            ]
+        );
+    }
+}
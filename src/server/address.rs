@@ -0,0 +1,95 @@
+use super::{ClientType, PeerReply};
+use std::net::{SocketAddr, SocketAddrV4, ToSocketAddrs};
+
+/// Where a `PeerReply` can be reached, as determined by its `client_type`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum PeerAddress {
+    Hostname {
+        hostname: String,
+        port: u16,
+    },
+    Ip(SocketAddrV4),
+    Email(String),
+    Deleted,
+    /// the entry lacks the address its `client_type` needs, e.g. a dynamic
+    /// entry that never sent a `ClientUpdate`
+    Unknown,
+}
+
+impl std::fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerAddress::Hostname { hostname, port } => write!(f, "{}:{}", hostname, port),
+            PeerAddress::Ip(address) => write!(f, "{}", address),
+            PeerAddress::Email(address) => write!(f, "{}", address),
+            PeerAddress::Deleted => write!(f, "deleted"),
+            PeerAddress::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Resolves hostnames to socket addresses.
+///
+/// `SystemResolver` uses the resolver of the operating system, tests can use
+/// any `Fn(&str, u16) -> std::io::Result<Vec<SocketAddr>>` instead.
+pub trait Resolver {
+    fn resolve(&self, hostname: &str, port: u16) -> std::io::Result<Vec<SocketAddr>>;
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, hostname: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        Ok((hostname, port).to_socket_addrs()?.collect())
+    }
+}
+
+impl<F> Resolver for F
+where
+    F: Fn(&str, u16) -> std::io::Result<Vec<SocketAddr>>,
+{
+    fn resolve(&self, hostname: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        self(hostname, port)
+    }
+}
+
+impl PeerReply {
+    pub fn address(&self) -> PeerAddress {
+        match self.client_type {
+            ClientType::Deleted => PeerAddress::Deleted,
+            ClientType::Email => match self.hostname() {
+                Some(address) => PeerAddress::Email(address.into()),
+                None => PeerAddress::Unknown,
+            },
+            ClientType::BaudotHostname | ClientType::AsciiHostname => match self.hostname() {
+                Some(hostname) => PeerAddress::Hostname {
+                    hostname: hostname.into(),
+                    port: self.port,
+                },
+                None => PeerAddress::Unknown,
+            },
+            ClientType::BaudotIpaddress | ClientType::AsciiIpaddress | ClientType::BaudotDynIp => {
+                match self.ipaddress() {
+                    Some(ipaddress) if !ipaddress.is_unspecified() => {
+                        PeerAddress::Ip(SocketAddrV4::new(*ipaddress, self.port))
+                    }
+                    _ => PeerAddress::Unknown,
+                }
+            }
+        }
+    }
+
+    /// The socket addresses this entry can be dialed at, resolving its
+    /// hostname with `resolver` if necessary.
+    pub fn to_socket_addrs(&self, resolver: &impl Resolver) -> std::io::Result<Vec<SocketAddr>> {
+        match self.address() {
+            PeerAddress::Hostname { hostname, port } => resolver.resolve(&hostname, port),
+            PeerAddress::Ip(address) => Ok(vec![address.into()]),
+            address => Err(std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("entry {} can not be dialed ({})", self.number, address),
+            )),
+        }
+    }
+}
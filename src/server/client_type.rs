@@ -1,4 +1,7 @@
-use std::convert::TryFrom;
+use crate::io;
+use crate::wire::{Decode, Encode};
+use core::convert::TryFrom;
+use core::fmt;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum ClientType {
@@ -11,39 +14,83 @@ pub enum ClientType {
     Email = 6,
 }
 
-impl<W> binserde::Serialize<W> for ClientType
-where
-    W: std::io::Write,
-{
-    fn serialize_ne(&self, _: &mut W) -> std::io::Result<()> {
-        panic!("I-Telex packages are always in Little Endian format")
+impl ClientType {
+    /// Whether entries of this type have their address updated by `ClientUpdate`s.
+    pub fn is_dynamic(self) -> bool {
+        self == ClientType::BaudotDynIp
     }
-    fn serialize_le(&self, writer: &mut W) -> std::io::Result<()> {
-        (*self as u8).serialize_le(writer)
+
+    /// A human readable name, as accepted by `FromStr`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ClientType::Deleted => "deleted",
+            ClientType::BaudotHostname => "baudot_hostname",
+            ClientType::BaudotIpaddress => "baudot_ipaddress",
+            ClientType::AsciiHostname => "ascii_hostname",
+            ClientType::AsciiIpaddress => "ascii_ipaddress",
+            ClientType::BaudotDynIp => "baudot_dynip",
+            ClientType::Email => "email",
+        }
+    }
+
+    /// Whether entries of this type are reached by their `hostname`.
+    pub fn uses_hostname(self) -> bool {
+        matches!(self, ClientType::BaudotHostname | ClientType::AsciiHostname)
+    }
+
+    /// Whether entries of this type are reached by a fixed `ipaddress`.
+    pub fn uses_ipaddress(self) -> bool {
+        matches!(
+            self,
+            ClientType::BaudotIpaddress | ClientType::AsciiIpaddress
+        )
     }
 }
 
-impl<R> binserde::Deserialize<R> for ClientType
-where
-    R: std::io::Read,
-{
-    fn deserialize_ne(_: &mut R) -> std::io::Result<Self> {
-        panic!("I-Telex packages are always in Little Endian format")
+impl Encode for ClientType {
+    fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        (*self as u8).encode(writer)
     }
-    fn deserialize_le(reader: &mut R) -> std::io::Result<Self> {
-        ClientType::try_from(u8::deserialize_le(reader)?)
-            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+impl Decode for ClientType {
+    fn decode(reader: &mut impl io::Read) -> io::Result<Self> {
+        ClientType::try_from(u8::decode(reader)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
     }
 }
 
-impl std::fmt::Display for ClientType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for ClientType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", *self as u8)
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ClientTypeError {
+    /// the wire value does not encode a client type
+    InvalidWireValue(u8),
+    /// the string is neither the name nor the value of a client type
+    InvalidString(String),
+}
+
+impl fmt::Display for ClientTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientTypeError::InvalidWireValue(byte) => {
+                write!(f, "{} is not a valid client type", byte)
+            }
+            ClientTypeError::InvalidString(string) => {
+                write!(f, "{:?} is not a valid client type", string)
+            }
+        }
+    }
+}
+
+impl core::error::Error for ClientTypeError {}
+
 impl TryFrom<u8> for ClientType {
-    type Error = std::io::Error;
+    type Error = ClientTypeError;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         Ok(match value {
@@ -55,11 +102,28 @@ impl TryFrom<u8> for ClientType {
             5 => Self::BaudotDynIp,
             6 => Self::Email,
 
-            _ => return Err(std::io::ErrorKind::InvalidInput.into()),
+            _ => return Err(ClientTypeError::InvalidWireValue(value)),
         })
     }
 }
 
+/// Parses either a name returned by `ClientType::name` or the numeric value.
+impl core::str::FromStr for ClientType {
+    type Err = ClientTypeError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if let Ok(value) = string.parse::<u8>() {
+            return ClientType::try_from(value)
+                .map_err(|_| ClientTypeError::InvalidString(string.into()));
+        }
+
+        (0..=6)
+            .filter_map(|value| ClientType::try_from(value).ok())
+            .find(|client_type| client_type.name().eq_ignore_ascii_case(string))
+            .ok_or_else(|| ClientTypeError::InvalidString(string.into()))
+    }
+}
+
 #[cfg(feature = "serde_serialize")]
 impl serde::Serialize for ClientType {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -78,6 +142,6 @@ impl<'de> serde::Deserialize<'de> for ClientType {
     {
         use serde::de::Error;
 
-        ClientType::try_from(u8::deserialize(deserializer)?).map_err(|err| D::Error::custom(err))
+        ClientType::try_from(u8::deserialize(deserializer)?).map_err(D::Error::custom)
     }
 }
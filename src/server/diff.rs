@@ -0,0 +1,325 @@
+//! Comparison of two snapshots of a directory, e.g. the results of a
+//! `FullQuery` on two servers that should be replicating each other.
+
+use super::PeerReply;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// A field of a `PeerReply`, apart from its number.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+pub enum Field {
+    Name,
+    Flags,
+    ClientType,
+    Hostname,
+    Ipaddress,
+    Port,
+    Extension,
+    Pin,
+    Timestamp,
+}
+
+impl Field {
+    pub const ALL: [Field; 9] = [
+        Field::Name,
+        Field::Flags,
+        Field::ClientType,
+        Field::Hostname,
+        Field::Ipaddress,
+        Field::Port,
+        Field::Extension,
+        Field::Pin,
+        Field::Timestamp,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Flags => "flags",
+            Field::ClientType => "client_type",
+            Field::Hostname => "hostname",
+            Field::Ipaddress => "ipaddress",
+            Field::Port => "port",
+            Field::Extension => "extension",
+            Field::Pin => "pin",
+            Field::Timestamp => "timestamp",
+        }
+    }
+
+    /// The value of this field of `entry`, formatted for display.
+    pub fn value(self, entry: &PeerReply) -> String {
+        match self {
+            Field::Name => format!("{:?}", entry.name.0),
+            Field::Flags => format!("{:#06x}", entry.flags.bits()),
+            Field::ClientType => entry.client_type.to_string(),
+            Field::Hostname => format!("{:?}", entry.hostname.0),
+            Field::Ipaddress => entry.ipaddress.to_string(),
+            Field::Port => entry.port.to_string(),
+            Field::Extension => entry.extension.to_string(),
+            Field::Pin => entry.pin.to_string(),
+            Field::Timestamp => entry.timestamp.to_string(),
+        }
+    }
+
+    fn differs(self, left: &PeerReply, right: &PeerReply) -> bool {
+        match self {
+            Field::Name => left.name != right.name,
+            Field::Flags => left.flags != right.flags,
+            Field::ClientType => left.client_type != right.client_type,
+            Field::Hostname => left.hostname != right.hostname,
+            Field::Ipaddress => left.ipaddress != right.ipaddress,
+            Field::Port => left.port != right.port,
+            Field::Extension => left.extension != right.extension,
+            Field::Pin => left.pin != right.pin,
+            Field::Timestamp => left.timestamp != right.timestamp,
+        }
+    }
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One of the two snapshots being compared.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// The difference between the entries for a single number.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum EntryDiff {
+    OnlyLeft(PeerReply),
+    OnlyRight(PeerReply),
+    Changed {
+        left: PeerReply,
+        right: PeerReply,
+        /// the fields that differ, in declaration order
+        fields: Vec<Field>,
+    },
+}
+
+impl EntryDiff {
+    pub fn number(&self) -> u32 {
+        match self {
+            EntryDiff::OnlyLeft(entry) | EntryDiff::OnlyRight(entry) => entry.number,
+            EntryDiff::Changed { left, .. } => left.number,
+        }
+    }
+
+    /// The side with the newer entry, or `None` if both have the same timestamp.
+    pub fn newer(&self) -> Option<Side> {
+        match self {
+            EntryDiff::OnlyLeft(_) => Some(Side::Left),
+            EntryDiff::OnlyRight(_) => Some(Side::Right),
+            EntryDiff::Changed { left, right, .. } => match left.timestamp.cmp(&right.timestamp) {
+                Ordering::Greater => Some(Side::Left),
+                Ordering::Less => Some(Side::Right),
+                Ordering::Equal => None,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for EntryDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryDiff::OnlyLeft(entry) => write!(f, "< {} {:?}", entry.number, entry.name.0),
+            EntryDiff::OnlyRight(entry) => write!(f, "> {} {:?}", entry.number, entry.name.0),
+            EntryDiff::Changed {
+                left,
+                right,
+                fields,
+            } => {
+                let newer = match self.newer() {
+                    Some(Side::Left) => "left is newer",
+                    Some(Side::Right) => "right is newer",
+                    None => "same timestamp",
+                };
+                write!(f, "~ {} ({})", left.number, newer)?;
+
+                for field in fields {
+                    write!(
+                        f,
+                        "\n    {}: {} -> {}",
+                        field,
+                        field.value(left),
+                        field.value(right)
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The differences between two snapshots, ordered by number.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct DirectoryDiff {
+    pub entries: Vec<EntryDiff>,
+}
+
+impl DirectoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl std::fmt::Display for DirectoryDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// The differing fields of two entries.
+pub fn diff_entries(left: &PeerReply, right: &PeerReply) -> Vec<Field> {
+    Field::ALL
+        .iter()
+        .copied()
+        .filter(|field| field.differs(left, right))
+        .collect()
+}
+
+/// Compare two snapshots, matching their entries by number.
+///
+/// If a snapshot contains a number more than once, its last entry is used.
+pub fn diff(left: &[PeerReply], right: &[PeerReply]) -> DirectoryDiff {
+    let mut sides: BTreeMap<u32, (Option<&PeerReply>, Option<&PeerReply>)> = BTreeMap::new();
+    for entry in left {
+        sides.entry(entry.number).or_default().0 = Some(entry);
+    }
+    for entry in right {
+        sides.entry(entry.number).or_default().1 = Some(entry);
+    }
+
+    let entries = sides
+        .into_values()
+        .filter_map(|sides| match sides {
+            (Some(left), None) => Some(EntryDiff::OnlyLeft(left.clone())),
+            (None, Some(right)) => Some(EntryDiff::OnlyRight(right.clone())),
+            (Some(left), Some(right)) => {
+                let fields = diff_entries(left, right);
+                if fields.is_empty() {
+                    None
+                } else {
+                    Some(EntryDiff::Changed {
+                        left: left.clone(),
+                        right: right.clone(),
+                        fields,
+                    })
+                }
+            }
+            (None, None) => unreachable!(),
+        })
+        .collect();
+
+    DirectoryDiff { entries }
+}
+
+/// Combine two snapshots, keeping the newer entry wherever both contain a
+/// number. On equal timestamps the entry from `left` is kept.
+///
+/// The result is ordered by number.
+pub fn merge(left: &[PeerReply], right: &[PeerReply]) -> Vec<PeerReply> {
+    let mut merged: BTreeMap<u32, PeerReply> = BTreeMap::new();
+    for entry in left {
+        merged.insert(entry.number, entry.clone());
+    }
+    for entry in right {
+        match merged.get(&entry.number) {
+            Some(current) if current.timestamp >= entry.timestamp => {}
+            _ => {
+                merged.insert(entry.number, entry.clone());
+            }
+        }
+    }
+
+    merged.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{ClientType, DirectoryTimestamp, PeerFlags};
+    use crate::Extension;
+    use std::net::Ipv4Addr;
+
+    fn entry(number: u32, name: &str, timestamp: u32) -> PeerReply {
+        PeerReply {
+            number,
+            name: name.into(),
+            flags: PeerFlags::empty(),
+            client_type: ClientType::AsciiIpaddress,
+            hostname: "".into(),
+            ipaddress: Ipv4Addr::new(192, 0, 2, 1),
+            port: 134,
+            extension: Extension::NONE,
+            pin: 0,
+            timestamp: DirectoryTimestamp::from_raw(0x8000_0000 + timestamp),
+        }
+    }
+
+    #[test]
+    fn diffs() {
+        let left = [entry(1, "A", 0), entry(2, "B", 0), entry(3, "C", 5)];
+        let right = [
+            entry(2, "B", 0),
+            PeerReply {
+                port: 135,
+                ..entry(3, "C2", 10)
+            },
+            entry(4, "D", 0),
+        ];
+
+        let diff = diff(&left, &right);
+        assert_eq!(
+            diff.entries
+                .iter()
+                .map(EntryDiff::number)
+                .collect::<Vec<_>>(),
+            vec![1, 3, 4]
+        );
+        assert_eq!(diff.entries[0], EntryDiff::OnlyLeft(entry(1, "A", 0)));
+        assert_eq!(diff.entries[2].newer(), Some(Side::Right));
+
+        match &diff.entries[1] {
+            EntryDiff::Changed { fields, .. } => {
+                assert_eq!(fields, &[Field::Name, Field::Port, Field::Timestamp])
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(diff.entries[1].newer(), Some(Side::Right));
+
+        assert!(diff_entries(&left[1], &right[0]).is_empty());
+        assert!(super::diff(&left, &left).is_empty());
+    }
+
+    #[test]
+    fn merges() {
+        let left = [entry(1, "A", 0), entry(2, "B new", 10), entry(3, "C", 5)];
+        let right = [entry(2, "B", 0), entry(3, "C new", 10), entry(4, "D", 0)];
+
+        let merged = merge(&left, &right);
+        assert_eq!(
+            merged,
+            vec![
+                entry(1, "A", 0),
+                entry(2, "B new", 10),
+                entry(3, "C new", 10),
+                entry(4, "D", 0)
+            ]
+        );
+
+        assert_eq!(
+            merge(&[entry(1, "A", 0)], &[entry(1, "B", 0)])[0].name.0,
+            "A"
+        );
+        assert!(diff(&merge(&left, &right), &merge(&right, &left)).is_empty());
+    }
+}
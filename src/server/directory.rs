@@ -0,0 +1,394 @@
+use super::text::{TextQuery, TextReply};
+use super::*;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Mutex, MutexGuard};
+
+/// A directory server answering requests from the entries of a `DirectoryStore`.
+pub struct DirectoryServer<S> {
+    store: Mutex<S>,
+    server_pin: u32,
+    pin_policy: Mutex<PinPolicy>,
+}
+
+impl<S: DirectoryStore + Send> DirectoryServer<S> {
+    /// A server on top of `store`, accepting `FullQuery`s and `Login`s with `server_pin`.
+    pub fn new(store: S, server_pin: u32) -> Self {
+        DirectoryServer {
+            store: Mutex::new(store),
+            server_pin,
+            pin_policy: Mutex::new(PinPolicy::default()),
+        }
+    }
+
+    pub fn with_pin_policy(mut self, pin_policy: PinPolicy) -> Self {
+        self.pin_policy = Mutex::new(pin_policy);
+        self
+    }
+
+    pub fn store(&self) -> MutexGuard<'_, S> {
+        self.store.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// An entry as it may be given out to the public.
+    fn public_entry(entry: PeerReply) -> Option<PeerReply> {
+        if entry.disabled() || entry.client_type == ClientType::Deleted {
+            None
+        } else {
+            Some(PeerReply { pin: 0, ..entry })
+        }
+    }
+
+    fn send_list(&self, session: &mut Session, entries: Vec<PeerReply>) -> std::io::Result<()> {
+        for entry in entries {
+            session.send(entry)?;
+            session.receive_acknowledge()?;
+        }
+
+        session.send(EndOfList {})
+    }
+
+    fn check_server_pin(&self, server_pin: u32) -> Result<(), Error> {
+        if server_pin == self.server_pin {
+            Ok(())
+        } else {
+            Err(Error::from(String::from("wrong server pin")))
+        }
+    }
+
+    fn client_update(&self, peer: IpAddr, update: &ClientUpdate) -> Result<Ipv4Addr, Error> {
+        let ipaddress = match peer {
+            IpAddr::V4(ipaddress) => ipaddress,
+            IpAddr::V6(ipaddress) => ipaddress
+                .to_ipv4_mapped()
+                .ok_or_else(|| Error::from(String::from("updates are only possible over IPv4")))?,
+        };
+
+        self.update(peer, ipaddress, update)?;
+
+        Ok(ipaddress)
+    }
+
+    /// Apply `update` sent by `source`, pointing the entry at `ipaddress`.
+    ///
+    /// This is what a `ClientUpdate` does, with `ipaddress` being the address
+    /// of `source`.
+    pub fn update(
+        &self,
+        source: IpAddr,
+        ipaddress: Ipv4Addr,
+        update: &ClientUpdate,
+    ) -> Result<(), Refusal> {
+        let mut store = self.store();
+        let mut entry = store.get(update.number).map_err(internal_refusal)?;
+
+        self.pin_policy()
+            .authorize(source, update, entry.as_mut())?;
+
+        let mut entry = entry.expect("PinPolicy accepted an update for a missing entry");
+        entry.ipaddress = ipaddress;
+        entry.port = update.port;
+        entry.timestamp = DirectoryTimestamp::now();
+        store.upsert(entry).map_err(internal_refusal)?;
+
+        Ok(())
+    }
+
+    /// Check `pin` for `number` the way an update from `source` would be
+    /// checked, without changing the entry. Entries without a pin never match.
+    pub fn verify_pin(&self, source: IpAddr, number: u32, pin: u16) -> Result<PeerReply, Refusal> {
+        let entry = self.store().get(number).map_err(internal_refusal)?;
+        self.pin_policy().verify(source, pin, entry.as_ref())?;
+
+        Ok(entry.expect("PinPolicy accepted a missing entry"))
+    }
+
+    fn pin_policy(&self) -> MutexGuard<'_, PinPolicy> {
+        self.pin_policy
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn peer_query(&self, query: &PeerQuery) -> Result<Package<Server>, Error> {
+        ProtocolVersion::negotiate(query.version, Server::PeerQuery)?;
+
+        let entry = self.store().get(query.number).map_err(internal_error)?;
+
+        Ok(match entry.and_then(Self::public_entry) {
+            Some(entry) => entry.into(),
+            None => PeerNotFound {}.into(),
+        })
+    }
+
+    fn peer_search(&self, search: &PeerSearch) -> Result<Vec<PeerReply>, Error> {
+        ProtocolVersion::negotiate(search.version, Server::PeerSearch)?;
+
+        Ok(self
+            .store()
+            .search(&search.pattern)
+            .map_err(internal_error)?
+            .into_iter()
+            .filter_map(Self::public_entry)
+            .collect())
+    }
+
+    fn full_query(&self, query: &FullQuery) -> Result<Vec<PeerReply>, Error> {
+        ProtocolVersion::negotiate(query.version, Server::FullQuery)?;
+        self.check_server_pin(query.server_pin)?;
+
+        self.store()
+            .changed_since(DirectoryTimestamp::NEVER)
+            .map_err(internal_error)
+    }
+
+    /// Entries pushed by other servers are only stored if they are consistent.
+    fn check_pushed_entry(entry: &PeerReply) -> Result<(), Error> {
+        let problems = entry.validate();
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        let problems: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        Err(Error::from(format!(
+            "invalid entry for {}: {}",
+            entry.number,
+            problems.join(", ")
+        )))
+    }
+
+    /// Receive the entries another server pushes to us after a `Login`.
+    fn login(&self, session: &mut Session, login: &Login) -> std::io::Result<()> {
+        let result = ProtocolVersion::negotiate(login.version, Server::Login)
+            .and_then(|_| self.check_server_pin(login.server_pin));
+        if let Err(err) = result {
+            return session.send(err);
+        }
+
+        session.send(Acknowledge {})?;
+
+        loop {
+            let package = session
+                .receive()?
+                .ok_or(std::io::ErrorKind::UnexpectedEof)?;
+
+            if package.is::<EndOfList>() {
+                return Ok(());
+            }
+
+            match package.downcast::<PeerReply>() {
+                Some(entry) => {
+                    if let Err(err) = Self::check_pushed_entry(&entry) {
+                        return session.send(err);
+                    }
+
+                    let mut store = self.store();
+                    let newer = match store.get(entry.number)? {
+                        Some(current) => entry.timestamp > current.timestamp,
+                        None => true,
+                    };
+                    if newer {
+                        store.upsert(*entry)?;
+                    }
+                    drop(store);
+
+                    session.send(Acknowledge {})?;
+                }
+                None => return session.send(unexpected_package()),
+            }
+        }
+    }
+}
+
+impl<S: DirectoryStore + Send> Service for DirectoryServer<S> {
+    fn call(&self, session: &mut Session, request: Package<Server>) -> std::io::Result<()> {
+        match request.package_type() {
+            Server::ClientUpdate => {
+                let update = request.downcast::<ClientUpdate>().unwrap();
+                match self.client_update(session.peer().ip(), &update) {
+                    Ok(ipaddress) => session.send(AddressConfirm { ipaddress }),
+                    Err(err) => session.send(err),
+                }
+            }
+            Server::PeerQuery => {
+                let query = request.downcast::<PeerQuery>().unwrap();
+                match self.peer_query(&query) {
+                    Ok(reply) => session.send(reply),
+                    Err(err) => session.send(err),
+                }
+            }
+            Server::PeerSearch => {
+                let search = request.downcast::<PeerSearch>().unwrap();
+                match self.peer_search(&search) {
+                    Ok(entries) => self.send_list(session, entries),
+                    Err(err) => session.send(err),
+                }
+            }
+            Server::FullQuery => {
+                let query = request.downcast::<FullQuery>().unwrap();
+                match self.full_query(&query) {
+                    Ok(entries) => self.send_list(session, entries),
+                    Err(err) => session.send(err),
+                }
+            }
+            Server::Login => {
+                let login = request.downcast::<Login>().unwrap();
+                self.login(session, &login)
+            }
+            _ => session.send(unexpected_package()),
+        }
+    }
+
+    fn text_query(&self, session: &mut Session, query: TextQuery) -> std::io::Result<()> {
+        match query {
+            TextQuery::Query(number) => {
+                let entry = self.store().get(number)?.and_then(Self::public_entry);
+                session.send_text_reply(&TextReply::new(number, entry.as_ref()))
+            }
+        }
+    }
+}
+
+fn unexpected_package() -> Error {
+    Error::from(String::from("unexpected package"))
+}
+
+fn internal_error(err: std::io::Error) -> Error {
+    internal_refusal(err).into()
+}
+
+fn internal_refusal(err: std::io::Error) -> Refusal {
+    Refusal::Internal(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Extension;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+
+    fn entry(number: u32, name: &str, client_type: ClientType) -> PeerReply {
+        PeerReply {
+            number,
+            name: name.into(),
+            flags: PeerFlags::empty(),
+            client_type,
+            hostname: "".into(),
+            ipaddress: Ipv4Addr::new(192, 0, 2, 1),
+            port: 134,
+            extension: Extension::NONE,
+            pin: 4711,
+            timestamp: DirectoryTimestamp::from_raw(0x8000_0000),
+        }
+    }
+
+    fn start_server() -> std::net::SocketAddr {
+        let mut store = MemoryStore::new();
+        store
+            .upsert(entry(1, "Alice", ClientType::BaudotDynIp))
+            .unwrap();
+        store
+            .upsert(entry(2, "Bob", ClientType::AsciiIpaddress))
+            .unwrap();
+        store
+            .upsert(PeerReply {
+                flags: PeerFlags::DISABLED,
+                ..entry(3, "Alice Disabled", ClientType::AsciiIpaddress)
+            })
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Arc::new(DirectoryServer::new(store, 0xdead_beef));
+        std::thread::spawn(move || listen(listener, server));
+
+        address
+    }
+
+    #[test]
+    fn queries() {
+        let address = start_server();
+        let mut client = DirectoryClient::connect(address).unwrap();
+
+        let reply = client.query(2).unwrap().unwrap();
+        assert_eq!(reply.name.0, "Bob");
+        assert_eq!(reply.pin, 0);
+
+        assert_eq!(client.query(3).unwrap(), None);
+        assert_eq!(client.query(4).unwrap(), None);
+
+        let found = client.search("alice").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].number, 1);
+
+        assert!(client.full_query(0).is_err());
+        let mut client = DirectoryClient::connect(address).unwrap();
+        assert_eq!(client.full_query(0xdead_beef).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn update_and_login() {
+        let address = start_server();
+
+        let mut client = DirectoryClient::connect(address).unwrap();
+        assert_eq!(
+            client.update(1, 4711, 1234).unwrap(),
+            Ipv4Addr::new(127, 0, 0, 1)
+        );
+        assert!(client.update(1, 1, 1234).is_err());
+
+        let reply = client.query(1).unwrap().unwrap();
+        assert_eq!(reply.ipaddress, Ipv4Addr::new(127, 0, 0, 1));
+        assert_eq!(reply.port, 1234);
+
+        let mut client = DirectoryClient::connect(address).unwrap();
+        client
+            .login(
+                0xdead_beef,
+                &[PeerReply {
+                    timestamp: DirectoryTimestamp::now(),
+                    ..entry(5, "Carol", ClientType::AsciiIpaddress)
+                }],
+            )
+            .unwrap();
+        assert_eq!(client.query(5).unwrap().unwrap().name.0, "Carol");
+    }
+
+    #[test]
+    fn login_rejects_invalid_entries() {
+        let address = start_server();
+
+        let mut client = DirectoryClient::connect(address).unwrap();
+        let err = client
+            .login(
+                0xdead_beef,
+                &[PeerReply {
+                    timestamp: DirectoryTimestamp::now(),
+                    ..entry(6, "Dave", ClientType::AsciiHostname)
+                }],
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid entry for 6: hostname is missing");
+
+        let mut client = DirectoryClient::connect(address).unwrap();
+        assert_eq!(client.query(6).unwrap(), None);
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let address = start_server();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        Package::<Server>::new(PeerQuery {
+            number: 1,
+            version: 0xff,
+        })
+        .serialize(&mut stream)
+        .unwrap();
+
+        let reply = Package::<Server>::deserialize(&mut stream).unwrap();
+        assert_eq!(
+            reply.downcast_ref::<Error>().unwrap().message,
+            "unsupported protocol version 255"
+        );
+    }
+}
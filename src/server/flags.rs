@@ -0,0 +1,143 @@
+use crate::io;
+use crate::wire::{Decode, Encode};
+
+/// The `flags` of a `PeerReply`.
+///
+/// Bits without a named constant are kept as they are, so that editing an
+/// entry does not clobber flags set by other implementations.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, Default)]
+pub struct PeerFlags(u16);
+
+impl PeerFlags {
+    /// The entry is disabled and should not be given out to clients.
+    pub const DISABLED: PeerFlags = PeerFlags(0x0002);
+
+    const KNOWN: u16 = Self::DISABLED.0;
+
+    pub const fn empty() -> Self {
+        PeerFlags(0)
+    }
+
+    /// Flags with exactly `bits` set, including unknown ones.
+    pub const fn from_bits(bits: u16) -> Self {
+        PeerFlags(bits)
+    }
+
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// The bits that do not belong to any known flag.
+    pub const fn unknown_bits(self) -> u16 {
+        self.0 & !Self::KNOWN
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, other: PeerFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: PeerFlags) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: PeerFlags) {
+        self.0 &= !other.0;
+    }
+
+    pub fn set(&mut self, other: PeerFlags, value: bool) {
+        if value {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+
+    pub fn disabled(self) -> bool {
+        self.contains(Self::DISABLED)
+    }
+
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.set(Self::DISABLED, disabled);
+    }
+}
+
+impl From<u16> for PeerFlags {
+    fn from(bits: u16) -> Self {
+        PeerFlags(bits)
+    }
+}
+
+impl From<PeerFlags> for u16 {
+    fn from(flags: PeerFlags) -> u16 {
+        flags.0
+    }
+}
+
+impl std::ops::BitOr for PeerFlags {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        PeerFlags(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for PeerFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl std::ops::BitAnd for PeerFlags {
+    type Output = Self;
+    fn bitand(self, other: Self) -> Self {
+        PeerFlags(self.0 & other.0)
+    }
+}
+
+impl std::ops::BitAndAssign for PeerFlags {
+    fn bitand_assign(&mut self, other: Self) {
+        self.0 &= other.0;
+    }
+}
+
+impl std::ops::Not for PeerFlags {
+    type Output = Self;
+    fn not(self) -> Self {
+        PeerFlags(!self.0)
+    }
+}
+
+impl Encode for PeerFlags {
+    fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        self.0.encode(writer)
+    }
+}
+
+impl Decode for PeerFlags {
+    fn decode(reader: &mut impl io::Read) -> io::Result<Self> {
+        u16::decode(reader).map(PeerFlags)
+    }
+}
+
+#[cfg(feature = "serde_serialize")]
+impl serde::Serialize for PeerFlags {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u16(self.0)
+    }
+}
+
+#[cfg(feature = "serde_deserialize")]
+impl<'de> serde::Deserialize<'de> for PeerFlags {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        u16::deserialize(deserializer).map(PeerFlags)
+    }
+}
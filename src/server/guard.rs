@@ -0,0 +1,512 @@
+use super::text::TextQuery;
+use super::{Error, Package, Server, Service, Session};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The limits enforced by a `Guard`.
+#[derive(Debug, Copy, Clone)]
+pub struct Limits {
+    /// connections a single address may have open at once
+    pub connections_per_ip: usize,
+    /// requests a single address may make per `request_interval`
+    pub requests_per_ip: u32,
+    pub request_interval: Duration,
+    /// the time after which a session that did not send anything is closed
+    pub idle_timeout: Duration,
+    /// `PeerSearch` and `FullQuery` requests being answered at once, from all addresses
+    pub concurrent_lists: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            connections_per_ip: 8,
+            requests_per_ip: 60,
+            request_interval: Duration::from_secs(60),
+            idle_timeout: Duration::from_secs(60),
+            concurrent_lists: 4,
+        }
+    }
+}
+
+/// A `Service` protecting another one from clients using too many resources.
+///
+/// Requests exceeding a limit are answered with an `Error` instead of being
+/// passed on, connections exceeding a limit are closed after sending one.
+pub struct Guard<S> {
+    inner: S,
+    limits: Limits,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    connections: HashMap<IpAddr, usize>,
+    requests: HashMap<IpAddr, Window>,
+    /// when the request windows of all addresses were last checked for expiry
+    requests_pruned: Option<Instant>,
+    lists: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+impl State {
+    /// Forget the request windows that ended, at most once per `interval`, so
+    /// that counting a request does not depend on the number of addresses.
+    fn prune_requests(&mut self, now: Instant, interval: Duration) {
+        if let Some(pruned) = self.requests_pruned {
+            if now.duration_since(pruned) < interval {
+                return;
+            }
+        }
+
+        self.requests
+            .retain(|_, window| now.duration_since(window.start) < interval);
+        self.requests_pruned = Some(now);
+    }
+}
+
+impl<S> Guard<S> {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn release_connection(&self, source: IpAddr) {
+        let mut state = self.state();
+        if let Some(connections) = state.connections.get_mut(&source) {
+            *connections -= 1;
+            if *connections == 0 {
+                state.connections.remove(&source);
+            }
+        }
+    }
+}
+
+impl<S: Service> Guard<S> {
+    pub fn new(inner: S, limits: Limits) -> Self {
+        Guard {
+            inner,
+            limits,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Count a request from `source`, failing if it made too many recently.
+    fn check_rate(&self, now: Instant, source: IpAddr) -> Result<(), Error> {
+        let interval = self.limits.request_interval;
+
+        let mut state = self.state();
+        state.prune_requests(now, interval);
+
+        let window = state.requests.entry(source).or_insert(Window {
+            start: now,
+            count: 0,
+        });
+        if now.duration_since(window.start) >= interval {
+            *window = Window {
+                start: now,
+                count: 0,
+            };
+        }
+        if window.count >= self.limits.requests_per_ip {
+            return Err(Error::from(String::from("too many requests")));
+        }
+        window.count += 1;
+
+        Ok(())
+    }
+
+    fn start_list(&self) -> Result<ListSlot<'_, S>, Error> {
+        let mut state = self.state();
+        if state.lists >= self.limits.concurrent_lists {
+            return Err(Error::from(String::from("server busy, try again later")));
+        }
+        state.lists += 1;
+
+        Ok(ListSlot { guard: self })
+    }
+}
+
+/// A running `PeerSearch` or `FullQuery`, counted until dropped.
+struct ListSlot<'g, S> {
+    guard: &'g Guard<S>,
+}
+
+impl<S> Drop for ListSlot<'_, S> {
+    fn drop(&mut self) {
+        self.guard.state().lists -= 1;
+    }
+}
+
+impl<S: Service> Service for Guard<S> {
+    fn connected(&self, session: &mut Session) -> std::io::Result<()> {
+        let source = session.peer().ip();
+
+        {
+            let mut state = self.state();
+            let connections = state.connections.entry(source).or_insert(0);
+            if *connections >= self.limits.connections_per_ip {
+                drop(state);
+                session.send(Error::from(String::from("too many connections")))?;
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionRefused,
+                    "too many connections",
+                ));
+            }
+            *connections += 1;
+        }
+
+        session.set_idle_timeout(Some(self.limits.idle_timeout));
+
+        let result = self.inner.connected(session);
+        if result.is_err() {
+            self.release_connection(source);
+        }
+        result
+    }
+
+    fn disconnected(&self, session: &Session) {
+        self.inner.disconnected(session);
+        self.release_connection(session.peer().ip());
+    }
+
+    fn accept_failed(&self, err: &std::io::Error) {
+        self.inner.accept_failed(err);
+    }
+
+    fn call(&self, session: &mut Session, request: Package<Server>) -> std::io::Result<()> {
+        if let Err(err) = self.check_rate(Instant::now(), session.peer().ip()) {
+            return session.send(err);
+        }
+
+        match request.package_type() {
+            Server::PeerSearch | Server::FullQuery => match self.start_list() {
+                Ok(_slot) => self.inner.call(session, request),
+                Err(err) => session.send(err),
+            },
+            _ => self.inner.call(session, request),
+        }
+    }
+
+    fn text_query(&self, session: &mut Session, query: TextQuery) -> std::io::Result<()> {
+        // the text protocol has no way to report errors, so just hang up
+        self.check_rate(Instant::now(), session.peer().ip())?;
+
+        self.inner.text_query(session, query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{Acknowledge, PeerQuery, PeerSearch};
+    use std::sync::Arc;
+
+    /// Answers every request with an `Acknowledge`, after waiting for `delay`.
+    struct Echo {
+        delay: Duration,
+    }
+
+    impl Service for Echo {
+        fn call(&self, session: &mut Session, _: Package<Server>) -> std::io::Result<()> {
+            std::thread::sleep(self.delay);
+            session.send(Acknowledge {})
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn packages(&self) -> Vec<Package<Server>> {
+            let buffer = self.0.lock().unwrap();
+            let mut reader = &buffer[..];
+
+            let mut packages = Vec::new();
+            while !reader.is_empty() {
+                packages.push(Package::<Server>::deserialize(&mut reader).unwrap());
+            }
+            packages
+        }
+    }
+
+    fn session(output: &Output, port: u16) -> Session {
+        Session::new(
+            std::io::empty(),
+            output.clone(),
+            ([192, 0, 2, 1], port).into(),
+        )
+    }
+
+    fn query() -> Package<Server> {
+        PeerQuery {
+            number: 1,
+            version: 1,
+        }
+        .into()
+    }
+
+    fn error_message(package: &Package<Server>) -> Option<&str> {
+        package
+            .downcast_ref::<Error>()
+            .map(|err| err.message.as_str())
+    }
+
+    #[test]
+    fn limits_connections() {
+        let guard = Guard::new(
+            Echo {
+                delay: Duration::from_secs(0),
+            },
+            Limits {
+                connections_per_ip: 2,
+                ..Limits::default()
+            },
+        );
+
+        let output = Output::default();
+        let mut sessions: Vec<Session> = (0..3).map(|port| session(&output, port)).collect();
+
+        assert!(guard.connected(&mut sessions[0]).is_ok());
+        assert!(guard.connected(&mut sessions[1]).is_ok());
+        assert!(guard.connected(&mut sessions[2]).is_err());
+        assert_eq!(
+            error_message(&output.packages()[0]),
+            Some("too many connections")
+        );
+        assert!(sessions[0].idle_timeout().is_some());
+
+        guard.disconnected(&sessions[0]);
+        assert!(guard.connected(&mut sessions[2]).is_ok());
+    }
+
+    #[test]
+    fn limits_request_rate() {
+        let guard = Guard::new(
+            Echo {
+                delay: Duration::from_secs(0),
+            },
+            Limits {
+                requests_per_ip: 2,
+                ..Limits::default()
+            },
+        );
+
+        let output = Output::default();
+        let mut session = session(&output, 0);
+        for _ in 0..3 {
+            guard.call(&mut session, query()).unwrap();
+        }
+
+        let packages = output.packages();
+        assert!(packages[0].is::<Acknowledge>());
+        assert!(packages[1].is::<Acknowledge>());
+        assert_eq!(error_message(&packages[2]), Some("too many requests"));
+
+        let now = Instant::now();
+        assert!(guard.check_rate(now, session.peer().ip()).is_err());
+        assert!(guard
+            .check_rate(now + Duration::from_secs(60), session.peer().ip())
+            .is_ok());
+
+        // windows of other addresses are only dropped once per interval
+        let other = IpAddr::from([192, 0, 2, 2]);
+        guard
+            .check_rate(now + Duration::from_secs(90), other)
+            .unwrap();
+        guard
+            .check_rate(now + Duration::from_secs(110), other)
+            .unwrap();
+        assert_eq!(guard.state().requests.len(), 2);
+        guard
+            .check_rate(now + Duration::from_secs(130), IpAddr::from([192, 0, 2, 3]))
+            .unwrap();
+        assert_eq!(guard.state().requests.len(), 2);
+        assert!(!guard.state().requests.contains_key(&session.peer().ip()));
+    }
+
+    #[test]
+    fn limits_concurrent_lists() {
+        let guard = Arc::new(Guard::new(
+            Echo {
+                delay: Duration::from_millis(200),
+            },
+            Limits {
+                concurrent_lists: 1,
+                ..Limits::default()
+            },
+        ));
+        let search = || {
+            Package::<Server>::new(PeerSearch {
+                version: 1,
+                pattern: "x".into(),
+            })
+        };
+
+        let first = Output::default();
+        let handle = {
+            let guard = guard.clone();
+            let mut session = session(&first, 0);
+            std::thread::spawn(move || guard.call(&mut session, search()).unwrap())
+        };
+        std::thread::sleep(Duration::from_millis(50));
+
+        let second = Output::default();
+        let mut second_session = session(&second, 1);
+        guard.call(&mut second_session, search()).unwrap();
+        guard.call(&mut second_session, query()).unwrap();
+
+        handle.join().unwrap();
+        guard.call(&mut second_session, search()).unwrap();
+
+        assert!(first.packages()[0].is::<Acknowledge>());
+        let packages = second.packages();
+        assert_eq!(
+            error_message(&packages[0]),
+            Some("server busy, try again later")
+        );
+        assert!(packages[1].is::<Acknowledge>());
+        assert!(packages[2].is::<Acknowledge>());
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let guard = Guard::new(
+            Echo {
+                delay: Duration::from_secs(0),
+            },
+            Limits {
+                idle_timeout: Duration::from_millis(100),
+                ..Limits::default()
+            },
+        );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let start = Instant::now();
+        assert!(crate::server::serve(&guard, Session::from_tcp(stream).unwrap()).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let reply = Package::<Server>::deserialize(&mut client).unwrap();
+        assert_eq!(error_message(&reply), Some("session timed out"));
+        assert!(guard.state().connections.is_empty());
+    }
+
+    #[test]
+    fn slow_lists_outlive_idle_timeout() {
+        use crate::server::{
+            ClientType, DirectoryServer, DirectoryStore, DirectoryTimestamp, EndOfList, FullQuery,
+            MemoryStore, PeerFlags, PeerReply,
+        };
+
+        let mut store = MemoryStore::new();
+        for number in 0..5 {
+            store
+                .upsert(PeerReply {
+                    number,
+                    name: "Test".into(),
+                    flags: PeerFlags::empty(),
+                    client_type: ClientType::BaudotDynIp,
+                    hostname: "".into(),
+                    ipaddress: std::net::Ipv4Addr::new(192, 0, 2, 1),
+                    port: 134,
+                    extension: crate::Extension::NONE,
+                    pin: 0,
+                    timestamp: DirectoryTimestamp::NEVER,
+                })
+                .unwrap();
+        }
+        let guard = Arc::new(Guard::new(
+            DirectoryServer::new(store, 1),
+            Limits {
+                idle_timeout: Duration::from_millis(100),
+                ..Limits::default()
+            },
+        ));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || crate::server::listen(listener, guard));
+
+        let mut client = std::net::TcpStream::connect(address).unwrap();
+        Package::<Server>::new(FullQuery {
+            version: 1,
+            server_pin: 1,
+        })
+        .serialize(&mut client)
+        .unwrap();
+
+        // acknowledging every entry in time keeps the session alive, although
+        // the whole list takes longer than the idle timeout
+        let start = Instant::now();
+        let mut entries = 0;
+        loop {
+            let package = Package::<Server>::deserialize(&mut client).unwrap();
+            if package.is::<EndOfList>() {
+                break;
+            }
+            assert!(package.is::<PeerReply>());
+            entries += 1;
+
+            std::thread::sleep(Duration::from_millis(60));
+            Package::<Server>::new(Acknowledge {})
+                .serialize(&mut client)
+                .unwrap();
+        }
+        assert_eq!(entries, 5);
+        assert!(start.elapsed() > Duration::from_millis(200));
+    }
+
+    #[test]
+    fn text_idle_timeout() {
+        use std::io::{Read, Write};
+
+        let guard = Guard::new(
+            Echo {
+                delay: Duration::from_secs(0),
+            },
+            Limits {
+                idle_timeout: Duration::from_millis(100),
+                ..Limits::default()
+            },
+        );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        // a query that is never finished
+        client.write_all(b"q12").unwrap();
+        assert!(crate::server::serve(&guard, Session::from_tcp(stream).unwrap()).is_err());
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).unwrap();
+        assert!(reply.is_empty());
+    }
+}
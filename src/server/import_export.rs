@@ -0,0 +1,280 @@
+//! Conversion of directory entries to and from CSV and JSON with human
+//! readable columns, e.g. for maintaining a directory in a spreadsheet.
+
+use super::{ClientType, DirectoryStore, DirectoryTimestamp, PeerFlags, PeerReply};
+use crate::Extension;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::Ipv4Addr;
+
+/// A `PeerReply` as a row of a CSV file or an object of a JSON array.
+#[derive(Debug, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DirectoryRecord {
+    pub number: u32,
+    pub name: String,
+    /// the `ClientType::name`, or its numeric value
+    pub client_type: String,
+    pub hostname: String,
+    /// a dotted ip address, empty if the entry has none
+    pub ipaddress: String,
+    pub port: u16,
+    /// the extension as displayed by `Extension`, `?` and the wire value if invalid
+    pub extension: String,
+    pub pin: u16,
+    pub disabled: bool,
+    /// all other flags
+    pub flags: u16,
+    /// an ISO 8601 timestamp as displayed by `DirectoryTimestamp`
+    pub timestamp: String,
+}
+
+impl From<&PeerReply> for DirectoryRecord {
+    fn from(entry: &PeerReply) -> Self {
+        let mut flags = entry.flags;
+        flags.set_disabled(false);
+
+        DirectoryRecord {
+            number: entry.number,
+            name: entry.name.0.clone(),
+            client_type: entry.client_type.name().into(),
+            hostname: entry.hostname.0.clone(),
+            ipaddress: entry
+                .ipaddress()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            port: entry.port,
+            extension: entry.extension.to_string(),
+            pin: entry.pin,
+            disabled: entry.disabled(),
+            flags: flags.bits(),
+            timestamp: entry.timestamp.to_string(),
+        }
+    }
+}
+
+impl TryFrom<DirectoryRecord> for PeerReply {
+    type Error = String;
+
+    fn try_from(record: DirectoryRecord) -> Result<Self, Self::Error> {
+        let mut flags = PeerFlags::from_bits(record.flags);
+        flags.set_disabled(record.disabled);
+
+        let ipaddress = if record.ipaddress.is_empty() {
+            Ipv4Addr::BROADCAST
+        } else {
+            record
+                .ipaddress
+                .parse()
+                .map_err(|_| format!("invalid ip address {:?}", record.ipaddress))?
+        };
+
+        let client_type = record
+            .client_type
+            .parse::<ClientType>()
+            .map_err(|err| err.to_string())?;
+        let extension = record
+            .extension
+            .parse::<Extension>()
+            .map_err(|err| err.to_string())?;
+        let timestamp = record
+            .timestamp
+            .parse::<DirectoryTimestamp>()
+            .map_err(|err| err.to_string())?;
+
+        Ok(PeerReply {
+            number: record.number,
+            name: record.name.into(),
+            flags,
+            client_type,
+            hostname: record.hostname.into(),
+            ipaddress,
+            port: record.port,
+            extension,
+            pin: record.pin,
+            timestamp,
+        })
+    }
+}
+
+/// A row that could not be imported.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct RowError {
+    /// the position of the row among all rows, starting at 1
+    pub row: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "row {}: {}", self.row, self.message)
+    }
+}
+
+impl std::error::Error for RowError {}
+
+/// The result of an import: all entries that could be read and errors for the rest.
+#[derive(Debug, Default, Clone)]
+pub struct Import {
+    pub entries: Vec<PeerReply>,
+    pub errors: Vec<RowError>,
+}
+
+impl Import {
+    fn push(&mut self, row: usize, record: Result<DirectoryRecord, String>) {
+        match record.and_then(PeerReply::try_from) {
+            Ok(entry) => self.entries.push(entry),
+            Err(message) => self.errors.push(RowError { row, message }),
+        }
+    }
+
+    /// Insert all imported entries into `store`.
+    pub fn seed(&self, store: &mut impl DirectoryStore) -> std::io::Result<()> {
+        for entry in &self.entries {
+            store.upsert(entry.clone())?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn export_csv(entries: &[PeerReply], writer: impl Write) -> std::io::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for entry in entries {
+        writer
+            .serialize(DirectoryRecord::from(entry))
+            .map_err(csv_error)?;
+    }
+
+    writer.flush()
+}
+
+pub fn export_json(entries: &[PeerReply], writer: impl Write) -> std::io::Result<()> {
+    let records: Vec<DirectoryRecord> = entries.iter().map(DirectoryRecord::from).collect();
+
+    serde_json::to_writer_pretty(writer, &records).map_err(std::io::Error::from)
+}
+
+pub fn import_csv(reader: impl Read) -> std::io::Result<Import> {
+    let mut import = Import::default();
+
+    let mut reader = csv::Reader::from_reader(reader);
+    for (i, record) in reader.deserialize::<DirectoryRecord>().enumerate() {
+        let record = match record {
+            Err(err) if err.is_io_error() => return Err(csv_error(err)),
+            record => record.map_err(|err| err.to_string()),
+        };
+
+        import.push(i + 1, record);
+    }
+
+    Ok(import)
+}
+
+/// Import a JSON array of `DirectoryRecord`s.
+pub fn import_json(reader: impl Read) -> std::io::Result<Import> {
+    let mut import = Import::default();
+
+    let values: Vec<serde_json::Value> =
+        serde_json::from_reader(reader).map_err(std::io::Error::from)?;
+    for (i, value) in values.into_iter().enumerate() {
+        import.push(
+            i + 1,
+            serde_json::from_value(value).map_err(|err| err.to_string()),
+        );
+    }
+
+    Ok(import)
+}
+
+fn csv_error(err: csv::Error) -> std::io::Error {
+    match err.into_kind() {
+        csv::ErrorKind::Io(err) => err,
+        kind => std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", kind)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::MemoryStore;
+
+    fn entries() -> Vec<PeerReply> {
+        vec![
+            PeerReply {
+                number: 1234,
+                name: "Erika Mustermann, Berlin".into(),
+                flags: PeerFlags::from_bits(0x8002),
+                client_type: ClientType::AsciiHostname,
+                hostname: "telex.example.org".into(),
+                ipaddress: Ipv4Addr::BROADCAST,
+                port: 134,
+                extension: "05".parse().unwrap(),
+                pin: 0,
+                timestamp: "2020-09-13T12:26:40Z".parse().unwrap(),
+            },
+            PeerReply {
+                number: 5678,
+                name: "Max".into(),
+                flags: PeerFlags::empty(),
+                client_type: ClientType::BaudotDynIp,
+                hostname: "".into(),
+                ipaddress: Ipv4Addr::new(192, 0, 2, 1),
+                port: 2342,
+                // not a valid extension, but kept as is
+                extension: Extension::from_wire(200),
+                pin: 4711,
+                timestamp: DirectoryTimestamp::NEVER,
+            },
+        ]
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let mut buffer = Vec::new();
+        export_csv(&entries(), &mut buffer).unwrap();
+
+        let csv = String::from_utf8(buffer.clone()).unwrap();
+        assert!(csv.starts_with(
+            "number,name,client_type,hostname,ipaddress,port,extension,pin,disabled,flags,timestamp\n"
+        ));
+        assert!(csv.contains(
+            "1234,\"Erika Mustermann, Berlin\",ascii_hostname,telex.example.org,,134,05,0,true,32768,2020-09-13T12:26:40Z\n"
+        ));
+
+        let import = import_csv(&buffer[..]).unwrap();
+        assert_eq!(import.errors, vec![]);
+        assert_eq!(import.entries, entries());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut buffer = Vec::new();
+        export_json(&entries(), &mut buffer).unwrap();
+
+        let import = import_json(&buffer[..]).unwrap();
+        assert_eq!(import.errors, vec![]);
+        assert_eq!(import.entries, entries());
+
+        let mut store = MemoryStore::new();
+        import.seed(&mut store).unwrap();
+        assert_eq!(store.get(5678).unwrap(), Some(entries()[1].clone()));
+    }
+
+    #[test]
+    fn reports_row_errors() {
+        let csv = "\
+number,name,client_type,hostname,ipaddress,port,extension,pin,disabled,flags,timestamp
+1,Good,email,a@example.org,,0,-,0,false,0,1900-01-01T00:00:00Z
+2,Bad Type,teleprinter,,,0,-,0,false,0,1900-01-01T00:00:00Z
+x,Bad Number,email,,,0,-,0,false,0,1900-01-01T00:00:00Z
+4,Bad Address,ascii_ipaddress,,1.2.3,134,-,0,false,0,1900-01-01T00:00:00Z
+";
+
+        let import = import_csv(csv.as_bytes()).unwrap();
+        assert_eq!(import.entries.len(), 1);
+        assert_eq!(
+            import.errors.iter().map(|err| err.row).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+    }
+}
@@ -24,3 +24,45 @@ pub use string_40_bytes::*;
 
 mod packages;
 pub use packages::*;
+
+mod store;
+pub use store::*;
+
+pub mod text;
+
+mod pin_policy;
+pub use pin_policy::*;
+
+mod validation;
+pub use validation::*;
+
+mod flags;
+pub use flags::*;
+
+mod timestamp;
+pub use timestamp::*;
+
+mod address;
+pub use address::*;
+
+mod version;
+pub use version::*;
+
+mod service;
+pub use service::*;
+
+mod directory;
+pub use directory::*;
+
+mod query;
+pub use query::*;
+
+pub mod search;
+
+#[cfg(feature = "import_export")]
+pub mod import_export;
+
+pub mod diff;
+
+mod guard;
+pub use guard::*;
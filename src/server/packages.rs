@@ -1,7 +1,11 @@
-use super::{ClientType, String40Bytes};
+use super::{ClientType, DirectoryTimestamp, PeerFlags, String40Bytes};
+use crate::io;
+use crate::wire::{Decode, Encode};
+use crate::Extension;
+use std::fmt;
 use std::net::Ipv4Addr;
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct ClientUpdate {
@@ -10,14 +14,14 @@ pub struct ClientUpdate {
     pub port: u16,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct AddressConfirm {
     pub ipaddress: Ipv4Addr,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct PeerQuery {
@@ -25,53 +29,41 @@ pub struct PeerQuery {
     pub version: u8,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct PeerNotFound {}
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct PeerReply {
     pub number: u32,
     pub name: String40Bytes,
-    pub flags: u16,
+    pub flags: PeerFlags,
     pub client_type: ClientType,
     pub hostname: String40Bytes,
     pub ipaddress: Ipv4Addr,
     pub port: u16,
-    pub extension: u8,
+    pub extension: Extension,
     pub pin: u16,
-    pub timestamp: u32,
+    pub timestamp: DirectoryTimestamp,
 }
 
 impl PeerReply {
     pub fn extension_as_str(&self) -> Result<String, u8> {
-        Ok(match self.extension {
-            0 => "-".into(),
-            100 => "00".into(),
-            110 => "0".into(),
-            x if x < 100 => format!("{:02}", x),
-            x if x > 100 && x < 110 => (x - 100).to_string(),
-            x => {
-                // x > 110
-                // entry has an invalid extension
-                return Err(x);
-            }
-        })
+        self.extension.as_string()
     }
 
     pub fn disabled(&self) -> bool {
-        self.flags & 2 == 0x02
+        self.flags.disabled()
     }
 
-    pub fn flags(disabled: bool) -> u16 {
-        if disabled {
-            0x02
-        } else {
-            0x00
-        }
+    #[deprecated(note = "use `PeerFlags`, which keeps all other bits, instead")]
+    pub fn flags(disabled: bool) -> PeerFlags {
+        let mut flags = PeerFlags::empty();
+        flags.set_disabled(disabled);
+        flags
     }
 
     pub fn hostname(&self) -> Option<&str> {
@@ -107,7 +99,7 @@ impl PeerReply {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct FullQuery {
@@ -115,7 +107,7 @@ pub struct FullQuery {
     pub server_pin: u32,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct Login {
@@ -123,21 +115,23 @@ pub struct Login {
     pub server_pin: u32,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct Acknowledge {}
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct EndOfList {}
 
-#[derive(Debug, Eq, PartialEq, Clone, binserde_derive::Serialize, binserde_derive::Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct PeerSearch {
     pub version: u8,
+    /// matches the entries whose name contains every whitespace separated
+    /// word of the pattern, ignoring case (see `server::search`)
     pub pattern: String40Bytes,
 }
 
@@ -154,34 +148,64 @@ impl From<String> for Error {
     }
 }
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.message)
     }
 }
 
-impl std::error::Error for Error {}
+impl core::error::Error for Error {}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        std::io::Error::other(err)
+    }
+}
+
+wire_struct! {
+    ClientUpdate { number, pin, port }
+    AddressConfirm { ipaddress }
+    PeerQuery { number, version }
+    PeerNotFound {}
+    PeerReply {
+        number,
+        name,
+        flags,
+        client_type,
+        hostname,
+        ipaddress,
+        port,
+        extension,
+        pin,
+        timestamp,
+    }
+    FullQuery { version, server_pin }
+    Login { version, server_pin }
+    Acknowledge {}
+    EndOfList {}
+    PeerSearch { version, pattern }
+}
 
-impl<W: std::io::Write> binserde::Serialize<W> for Error {
-    fn serialize_ne(&self, writer: &mut W) -> std::io::Result<()> {
+impl Encode for Error {
+    fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
         writer.write_all(self.message.as_bytes())?;
         writer.write_all(&[0])?;
 
         Ok(())
     }
 }
-impl<R: std::io::Read> binserde::Deserialize<R> for Error {
-    fn deserialize_ne(reader: &mut R) -> std::io::Result<Self> {
+impl Decode for Error {
+    fn decode(reader: &mut impl io::Read) -> io::Result<Self> {
         let mut buffer = Vec::new();
         loop {
-            let byte = u8::deserialize_ne(reader)?;
+            let byte = u8::decode(reader)?;
 
             if byte != 0 {
                 buffer.push(byte);
             } else {
                 return Ok(Error {
                     message: String::from_utf8(buffer)
-                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?,
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
                 });
             }
         }
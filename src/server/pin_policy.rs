@@ -0,0 +1,414 @@
+use super::{ClientType, ClientUpdate, Error, PeerReply};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Decides whether a `ClientUpdate` may change a directory entry.
+///
+/// The first update carrying a pin for an entry without a pin sets it, every
+/// later update has to carry the same pin. Sources that repeatedly fail are
+/// locked out for a while.
+#[derive(Debug, Clone)]
+pub struct PinPolicy {
+    max_failures: u32,
+    lockout: Duration,
+    failures: HashMap<IpAddr, Failures>,
+    /// when the failures of all sources were last checked for expiry
+    pruned: Option<Instant>,
+}
+
+/// The messages of the `Error`s a directory server refuses updates with.
+///
+/// These are part of the protocol: the `RemoteAuthenticator` of a centralex
+/// relay matches them exactly to tell why an update was refused, so they must
+/// never change.
+pub mod refusal_message {
+    pub const UNKNOWN_NUMBER: &str = "unknown number";
+    pub const WRONG_PIN: &str = "wrong pin";
+    pub const RATE_LIMITED: &str = "too many failed updates";
+    /// followed by a description of the error
+    pub const INTERNAL_PREFIX: &str = "internal error: ";
+    /// followed by the numeric client type and `NOT_UPDATABLE_SUFFIX`
+    pub const NOT_UPDATABLE_PREFIX: &str = "entries of client type ";
+    pub const NOT_UPDATABLE_SUFFIX: &str = " can not be updated";
+}
+
+/// Why an update or a pin was refused.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Refusal {
+    UnknownNumber,
+    WrongPin,
+    /// entries of this client type do not take updates
+    NotUpdatable(ClientType),
+    /// the source failed too often and is locked out for a while
+    RateLimited,
+    /// the entry could not be read or written
+    Internal(String),
+}
+
+impl Refusal {
+    /// The `Refusal` an `Error` sent by a directory server stands for, if any.
+    pub fn from_message(message: &str) -> Option<Self> {
+        Some(match message {
+            refusal_message::UNKNOWN_NUMBER => Refusal::UnknownNumber,
+            refusal_message::WRONG_PIN => Refusal::WrongPin,
+            refusal_message::RATE_LIMITED => Refusal::RateLimited,
+            message => {
+                if let Some(err) = message.strip_prefix(refusal_message::INTERNAL_PREFIX) {
+                    Refusal::Internal(err.into())
+                } else {
+                    let client_type = message
+                        .strip_prefix(refusal_message::NOT_UPDATABLE_PREFIX)?
+                        .strip_suffix(refusal_message::NOT_UPDATABLE_SUFFIX)?;
+                    Refusal::NotUpdatable(
+                        ClientType::try_from(client_type.parse::<u8>().ok()?).ok()?,
+                    )
+                }
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Refusal::UnknownNumber => f.write_str(refusal_message::UNKNOWN_NUMBER),
+            Refusal::WrongPin => f.write_str(refusal_message::WRONG_PIN),
+            Refusal::NotUpdatable(client_type) => write!(
+                f,
+                "{}{}{}",
+                refusal_message::NOT_UPDATABLE_PREFIX,
+                client_type,
+                refusal_message::NOT_UPDATABLE_SUFFIX
+            ),
+            Refusal::RateLimited => f.write_str(refusal_message::RATE_LIMITED),
+            Refusal::Internal(err) => write!(f, "{}{}", refusal_message::INTERNAL_PREFIX, err),
+        }
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+impl From<Refusal> for Error {
+    fn from(refusal: Refusal) -> Self {
+        Error::from(refusal.to_string())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Default for PinPolicy {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(15 * 60))
+    }
+}
+
+impl PinPolicy {
+    /// Lock out a source for `lockout` after `max_failures` failed updates.
+    pub fn new(max_failures: u32, lockout: Duration) -> Self {
+        PinPolicy {
+            max_failures,
+            lockout,
+            failures: HashMap::new(),
+            pruned: None,
+        }
+    }
+
+    /// Check `update` sent by `source` against the current `entry` for its number.
+    ///
+    /// On success the pin of `entry` is set if it did not have one yet, the
+    /// caller is responsible for applying the rest of the update.
+    pub fn authorize(
+        &mut self,
+        source: IpAddr,
+        update: &ClientUpdate,
+        entry: Option<&mut PeerReply>,
+    ) -> Result<(), Refusal> {
+        self.authorize_at(Instant::now(), source, update, entry)
+    }
+
+    pub fn authorize_at(
+        &mut self,
+        now: Instant,
+        source: IpAddr,
+        update: &ClientUpdate,
+        mut entry: Option<&mut PeerReply>,
+    ) -> Result<(), Refusal> {
+        self.check(now, source, update.pin, entry.as_deref(), true)?;
+
+        if let Some(entry) = entry.as_mut() {
+            if entry.pin == 0 {
+                entry.pin = update.pin;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check `pin` sent by `source` for `entry` like `authorize`, without
+    /// changing the entry. Entries without a pin never match.
+    pub fn verify(
+        &mut self,
+        source: IpAddr,
+        pin: u16,
+        entry: Option<&PeerReply>,
+    ) -> Result<(), Refusal> {
+        self.verify_at(Instant::now(), source, pin, entry)
+    }
+
+    pub fn verify_at(
+        &mut self,
+        now: Instant,
+        source: IpAddr,
+        pin: u16,
+        entry: Option<&PeerReply>,
+    ) -> Result<(), Refusal> {
+        self.check(now, source, pin, entry, false)
+    }
+
+    /// Check `pin` for `entry`, counting failures of `source`. An entry
+    /// without a pin accepts any other pin if `may_set_pin`.
+    fn check(
+        &mut self,
+        now: Instant,
+        source: IpAddr,
+        pin: u16,
+        entry: Option<&PeerReply>,
+        may_set_pin: bool,
+    ) -> Result<(), Refusal> {
+        if self.is_locked_out(now, source) {
+            return Err(Refusal::RateLimited);
+        }
+
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return Err(self.fail(now, source, Refusal::UnknownNumber)),
+        };
+
+        if !entry.client_type.is_dynamic() {
+            return Err(Refusal::NotUpdatable(entry.client_type));
+        }
+
+        let sets_pin = may_set_pin && entry.pin == 0 && pin != 0;
+        if !sets_pin && (entry.pin == 0 || pin != entry.pin) {
+            return Err(self.fail(now, source, Refusal::WrongPin));
+        }
+
+        self.failures.remove(&source);
+
+        Ok(())
+    }
+
+    fn is_locked_out(&mut self, now: Instant, source: IpAddr) -> bool {
+        match self.failures.get(&source) {
+            Some(failures) if now.duration_since(failures.last) >= self.lockout => {
+                self.failures.remove(&source);
+                false
+            }
+            Some(failures) => failures.count >= self.max_failures,
+            None => false,
+        }
+    }
+
+    fn fail(&mut self, now: Instant, source: IpAddr, refusal: Refusal) -> Refusal {
+        self.prune(now);
+
+        let lockout = self.lockout;
+        let failures = self.failures.entry(source).or_insert(Failures {
+            count: 0,
+            last: now,
+        });
+        if now.duration_since(failures.last) >= lockout {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last = now;
+
+        refusal
+    }
+
+    /// Forget the failures of sources that have not failed for `lockout`.
+    ///
+    /// This only runs once per `lockout`, so that failing stays cheap no matter
+    /// how many sources are failing.
+    fn prune(&mut self, now: Instant) {
+        let lockout = self.lockout;
+        if let Some(pruned) = self.pruned {
+            if now.duration_since(pruned) < lockout {
+                return;
+            }
+        }
+
+        self.failures
+            .retain(|_, failures| now.duration_since(failures.last) < lockout);
+        self.pruned = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{ClientType, DirectoryTimestamp, PeerFlags};
+    use crate::Extension;
+    use std::net::Ipv4Addr;
+
+    fn entry(client_type: ClientType, pin: u16) -> PeerReply {
+        PeerReply {
+            number: 1234,
+            name: "Test".into(),
+            flags: PeerFlags::empty(),
+            client_type,
+            hostname: "".into(),
+            ipaddress: Ipv4Addr::BROADCAST,
+            port: 134,
+            extension: Extension::NONE,
+            pin,
+            timestamp: DirectoryTimestamp::NEVER,
+        }
+    }
+
+    fn update(pin: u16) -> ClientUpdate {
+        ClientUpdate {
+            number: 1234,
+            pin,
+            port: 134,
+        }
+    }
+
+    const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    #[test]
+    fn first_update_sets_pin() {
+        let mut policy = PinPolicy::default();
+        let mut entry = entry(ClientType::BaudotDynIp, 0);
+
+        policy
+            .authorize(SOURCE, &update(4711), Some(&mut entry))
+            .unwrap();
+        assert_eq!(entry.pin, 4711);
+
+        policy
+            .authorize(SOURCE, &update(4711), Some(&mut entry))
+            .unwrap();
+        assert_eq!(
+            policy.authorize(SOURCE, &update(1), Some(&mut entry)),
+            Err(Refusal::WrongPin)
+        );
+        assert_eq!(entry.pin, 4711);
+    }
+
+    #[test]
+    fn refuses_static_entries() {
+        let mut policy = PinPolicy::default();
+        let mut entry = entry(ClientType::BaudotHostname, 4711);
+
+        assert_eq!(
+            policy.authorize(SOURCE, &update(4711), Some(&mut entry)),
+            Err(Refusal::NotUpdatable(ClientType::BaudotHostname))
+        );
+        assert_eq!(
+            policy.authorize(SOURCE, &update(4711), None),
+            Err(Refusal::UnknownNumber)
+        );
+    }
+
+    #[test]
+    fn rate_limits_failures() {
+        let mut policy = PinPolicy::new(2, Duration::from_secs(60));
+        let mut entry = entry(ClientType::BaudotDynIp, 4711);
+        let start = Instant::now();
+
+        for _ in 0..2 {
+            assert!(policy
+                .authorize_at(start, SOURCE, &update(1), Some(&mut entry))
+                .is_err());
+        }
+
+        // the correct pin is refused while the source is locked out
+        assert_eq!(
+            policy.authorize_at(start, SOURCE, &update(4711), Some(&mut entry)),
+            Err(Refusal::RateLimited)
+        );
+
+        policy
+            .authorize_at(
+                start + Duration::from_secs(60),
+                SOURCE,
+                &update(4711),
+                Some(&mut entry),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn verify_counts_unset_pins() {
+        let mut policy = PinPolicy::new(2, Duration::from_secs(60));
+        let unset = entry(ClientType::BaudotDynIp, 0);
+        let start = Instant::now();
+
+        for _ in 0..2 {
+            assert_eq!(
+                policy.verify_at(start, SOURCE, 4711, Some(&unset)),
+                Err(Refusal::WrongPin)
+            );
+        }
+        assert_eq!(
+            policy.verify_at(start, SOURCE, 4711, Some(&unset)),
+            Err(Refusal::RateLimited)
+        );
+
+        let set = entry(ClientType::BaudotDynIp, 4711);
+        policy
+            .verify_at(start + Duration::from_secs(60), SOURCE, 4711, Some(&set))
+            .unwrap();
+    }
+
+    #[test]
+    fn forgets_stale_failures() {
+        let mut policy = PinPolicy::new(2, Duration::from_secs(60));
+        let mut entry = entry(ClientType::BaudotDynIp, 4711);
+        let start = Instant::now();
+        let source = |last_octet| IpAddr::from([192, 0, 2, last_octet]);
+
+        let mut fail = |now, source| {
+            assert!(policy
+                .authorize_at(now, source, &update(1), Some(&mut entry))
+                .is_err());
+        };
+        fail(start, source(1));
+        fail(start + Duration::from_secs(30), source(2));
+        fail(start + Duration::from_secs(70), source(3));
+
+        // the first source expired, the second one is not checked again yet
+        let sources: std::collections::HashSet<_> = policy.failures.keys().copied().collect();
+        assert_eq!(sources, [source(2), source(3)].iter().copied().collect());
+    }
+
+    #[test]
+    fn refusal_messages() {
+        for (refusal, message) in &[
+            (Refusal::UnknownNumber, refusal_message::UNKNOWN_NUMBER),
+            (Refusal::WrongPin, refusal_message::WRONG_PIN),
+            (
+                Refusal::NotUpdatable(ClientType::AsciiIpaddress),
+                "entries of client type 4 can not be updated",
+            ),
+            (Refusal::RateLimited, refusal_message::RATE_LIMITED),
+            (
+                Refusal::Internal(String::from("disk full")),
+                "internal error: disk full",
+            ),
+        ] {
+            let error = Error::from(refusal.clone());
+            assert_eq!(error.message, *message);
+            assert_eq!(Refusal::from_message(message).as_ref(), Some(refusal));
+        }
+
+        assert_eq!(Refusal::from_message("wrong server pin"), None);
+    }
+}
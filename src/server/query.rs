@@ -0,0 +1,141 @@
+use super::*;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, TcpStream, ToSocketAddrs};
+
+/// A client making requests to a directory server.
+#[derive(Debug)]
+pub struct DirectoryClient<T = TcpStream> {
+    stream: T,
+    version: ProtocolVersion,
+}
+
+impl DirectoryClient<TcpStream> {
+    pub fn connect(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Ok(Self::new(TcpStream::connect(address)?))
+    }
+}
+
+impl<T: Read + Write> DirectoryClient<T> {
+    pub fn new(stream: T) -> Self {
+        DirectoryClient {
+            stream,
+            version: ProtocolVersion::CURRENT,
+        }
+    }
+
+    /// Make requests with `version` instead of `ProtocolVersion::CURRENT`.
+    pub fn with_version(mut self, version: ProtocolVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+
+    /// Look up the entry for `number`.
+    pub fn query(&mut self, number: u32) -> std::io::Result<Option<PeerReply>> {
+        self.send(PeerQuery {
+            number,
+            version: self.version.into(),
+        })?;
+
+        let reply = self.receive()?;
+
+        if reply.is::<PeerNotFound>() {
+            Ok(None)
+        } else {
+            expect::<PeerReply>(reply).map(Some)
+        }
+    }
+
+    /// All public entries whose name matches `pattern`.
+    pub fn search(&mut self, pattern: &str) -> std::io::Result<Vec<PeerReply>> {
+        self.send(PeerSearch {
+            version: self.version.into(),
+            pattern: pattern.into(),
+        })?;
+
+        self.receive_list()
+    }
+
+    /// All entries of the directory, including private fields.
+    pub fn full_query(&mut self, server_pin: u32) -> std::io::Result<Vec<PeerReply>> {
+        self.send(FullQuery {
+            version: self.version.into(),
+            server_pin,
+        })?;
+
+        self.receive_list()
+    }
+
+    /// Push `entries` to another server.
+    pub fn login(&mut self, server_pin: u32, entries: &[PeerReply]) -> std::io::Result<()> {
+        self.send(Login {
+            version: self.version.into(),
+            server_pin,
+        })?;
+        expect::<Acknowledge>(self.receive()?)?;
+
+        for entry in entries {
+            self.send(entry.clone())?;
+            expect::<Acknowledge>(self.receive()?)?;
+        }
+
+        self.send(EndOfList {})
+    }
+
+    /// Update the address of the dynamic entry for `number` to our address
+    /// and `port`, returning our address as seen by the server.
+    pub fn update(&mut self, number: u32, pin: u16, port: u16) -> std::io::Result<Ipv4Addr> {
+        self.send(ClientUpdate { number, pin, port })?;
+
+        expect::<AddressConfirm>(self.receive()?).map(|confirm| confirm.ipaddress)
+    }
+
+    fn receive_list(&mut self) -> std::io::Result<Vec<PeerReply>> {
+        let mut entries = Vec::new();
+        loop {
+            let package = self.receive()?;
+            if package.is::<EndOfList>() {
+                return Ok(entries);
+            }
+
+            entries.push(expect::<PeerReply>(package)?);
+            self.send(Acknowledge {})?;
+        }
+    }
+
+    fn send(&mut self, package: impl Into<Package<Server>>) -> std::io::Result<()> {
+        let mut buffer = Vec::new();
+        package.into().serialize(&mut buffer)?;
+
+        self.stream.write_all(&buffer)?;
+        self.stream.flush()
+    }
+
+    fn receive(&mut self) -> std::io::Result<Package<Server>> {
+        Package::<Server>::deserialize(&mut self.stream)
+    }
+}
+
+/// Turn `package` into a `P`, or into an error if the server sent an `Error`
+/// or anything else.
+fn expect<P: PackageBody<Class = Server>>(package: Package<Server>) -> std::io::Result<P> {
+    if let Some(err) = package.downcast_ref::<Error>() {
+        return Err(err.clone().into());
+    }
+
+    if package.is::<P>() {
+        Ok(*package.downcast::<P>().unwrap())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unexpected package {:?}", package),
+        ))
+    }
+}
@@ -0,0 +1,202 @@
+//! The matching used to answer `PeerSearch` requests.
+//!
+//! A pattern matches an entry if every whitespace separated word of the
+//! pattern appears somewhere in the entry's name, ignoring case. Disabled and
+//! deleted entries never match, neither does a pattern without any words.
+
+use super::{ClientType, PeerReply};
+use std::collections::{BTreeSet, HashMap};
+
+/// Whether `entry` is a result of searching for `pattern`.
+pub fn matches(pattern: &str, entry: &PeerReply) -> bool {
+    let words = words(pattern);
+
+    is_searchable(entry) && !words.is_empty() && contains_all(&entry.name.to_lowercase(), &words)
+}
+
+fn is_searchable(entry: &PeerReply) -> bool {
+    !entry.disabled() && entry.client_type != ClientType::Deleted
+}
+
+fn words(pattern: &str) -> Vec<String> {
+    pattern.split_whitespace().map(str::to_lowercase).collect()
+}
+
+fn contains_all(name: &str, words: &[String]) -> bool {
+    words.iter().all(|word| name.contains(word.as_str()))
+}
+
+type Trigram = [char; 3];
+
+fn trigrams(string: &str) -> Vec<Trigram> {
+    let chars: Vec<char> = string.chars().collect();
+    chars
+        .windows(3)
+        .map(|window| [window[0], window[1], window[2]])
+        .collect()
+}
+
+/// An index over the names of a directory, giving the same results as
+/// `matches` without looking at every entry.
+///
+/// Words of three or more characters are looked up by their trigrams, the
+/// candidates found that way are then checked with `matches`' rules.
+#[derive(Debug, Default, Clone)]
+pub struct SearchIndex {
+    names: HashMap<u32, String>,
+    trigrams: HashMap<Trigram, BTreeSet<u32>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `entry` to the index, replacing a previous entry with the same number.
+    pub fn insert(&mut self, entry: &PeerReply) {
+        self.remove(entry.number);
+
+        if !is_searchable(entry) {
+            return;
+        }
+
+        let name = entry.name.to_lowercase();
+        for trigram in trigrams(&name) {
+            self.trigrams
+                .entry(trigram)
+                .or_default()
+                .insert(entry.number);
+        }
+        self.names.insert(entry.number, name);
+    }
+
+    pub fn remove(&mut self, number: u32) {
+        if let Some(name) = self.names.remove(&number) {
+            for trigram in trigrams(&name) {
+                if let Some(numbers) = self.trigrams.get_mut(&trigram) {
+                    numbers.remove(&number);
+                    if numbers.is_empty() {
+                        self.trigrams.remove(&trigram);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The numbers of all entries matching `pattern`, in ascending order.
+    pub fn search(&self, pattern: &str) -> Vec<u32> {
+        let words = words(pattern);
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        let mut candidates: Option<BTreeSet<u32>> = None;
+        for trigram in words.iter().flat_map(|word| trigrams(word)) {
+            let numbers = match self.trigrams.get(&trigram) {
+                Some(numbers) => numbers,
+                None => return Vec::new(),
+            };
+
+            candidates = Some(match candidates {
+                Some(candidates) => candidates.intersection(numbers).copied().collect(),
+                None => numbers.clone(),
+            });
+        }
+
+        let matching = |number: &u32| contains_all(&self.names[number], &words);
+        match candidates {
+            Some(candidates) => candidates.into_iter().filter(matching).collect(),
+            None => {
+                let mut numbers: Vec<u32> = self.names.keys().copied().filter(matching).collect();
+                numbers.sort_unstable();
+                numbers
+            }
+        }
+    }
+}
+
+impl<'a> std::iter::FromIterator<&'a PeerReply> for SearchIndex {
+    fn from_iter<I: IntoIterator<Item = &'a PeerReply>>(entries: I) -> Self {
+        let mut index = SearchIndex::new();
+        for entry in entries {
+            index.insert(entry);
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{DirectoryTimestamp, PeerFlags};
+    use crate::Extension;
+    use std::net::Ipv4Addr;
+
+    fn entry(number: u32, name: &str) -> PeerReply {
+        PeerReply {
+            number,
+            name: name.into(),
+            flags: PeerFlags::empty(),
+            client_type: ClientType::AsciiHostname,
+            hostname: "example.org".into(),
+            ipaddress: Ipv4Addr::BROADCAST,
+            port: 134,
+            extension: Extension::NONE,
+            pin: 0,
+            timestamp: DirectoryTimestamp::NEVER,
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let entry = entry(1, "Erika Mustermann, Berlin");
+
+        assert!(matches("erika", &entry));
+        assert!(matches("  BERLIN   muster ", &entry));
+        // every word has to match, in any order
+        assert!(matches("berlin erika", &entry));
+        assert!(!matches("erika hamburg", &entry));
+        assert!(!matches("", &entry));
+
+        let disabled = PeerReply {
+            flags: PeerFlags::DISABLED,
+            ..entry.clone()
+        };
+        assert!(!matches("erika", &disabled));
+
+        let deleted = PeerReply {
+            client_type: ClientType::Deleted,
+            ..entry
+        };
+        assert!(!matches("erika", &deleted));
+    }
+
+    #[test]
+    fn index_agrees_with_matches() {
+        let entries = [
+            entry(1, "Erika Mustermann, Berlin"),
+            entry(2, "Max Mustermann, Hamburg"),
+            entry(3, "Telex Museum"),
+            PeerReply {
+                flags: PeerFlags::DISABLED,
+                ..entry(4, "Erika Disabled")
+            },
+        ];
+        let mut index: SearchIndex = entries.iter().collect();
+
+        for pattern in &["muster", "erika", "mu", "ham muster", "xyz", "", "m"] {
+            let expected: Vec<u32> = entries
+                .iter()
+                .filter(|entry| matches(pattern, entry))
+                .map(|entry| entry.number)
+                .collect();
+            assert_eq!(index.search(pattern), expected, "pattern {:?}", pattern);
+        }
+
+        index.insert(&entry(2, "Renamed"));
+        assert_eq!(index.search("muster"), vec![1]);
+
+        index.remove(1);
+        assert_eq!(index.search("muster"), Vec::<u32>::new());
+    }
+}
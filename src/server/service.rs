@@ -0,0 +1,364 @@
+use super::text::{sniff_protocol, Protocol, TextQuery, TextReply};
+use super::{Acknowledge, Error, Package, Server};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The number of connections `listen` serves at once.
+pub const MAX_WORKERS: usize = 256;
+
+/// How long `listen` waits before accepting again after accepting failed.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Answers the requests of clients connected to the directory port.
+pub trait Service: Send + Sync {
+    /// Called when a client connected, before any of its requests are read.
+    /// Returning an error closes the connection.
+    fn connected(&self, _session: &mut Session) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Called when the connection of a client accepted by `connected` ended.
+    fn disconnected(&self, _session: &Session) {}
+
+    /// Called when accepting a connection failed, e.g. because the process ran
+    /// out of file descriptors. `listen` tries again after a short pause.
+    fn accept_failed(&self, _err: &std::io::Error) {}
+
+    /// Handle a single binary `request`, sending any replies through `session`.
+    fn call(&self, session: &mut Session, request: Package<Server>) -> std::io::Result<()>;
+
+    /// Handle a query of the text protocol.
+    fn text_query(&self, session: &mut Session, query: TextQuery) -> std::io::Result<()> {
+        match query {
+            TextQuery::Query(number) => session.send_text_reply(&TextReply::NotFound(number)),
+        }
+    }
+}
+
+/// A connection of a client to the directory port.
+pub struct Session {
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: Box<dyn Write + Send>,
+    peer: SocketAddr,
+    stream: Option<TcpStream>,
+    deadline: Option<Instant>,
+    idle_timeout: Option<Duration>,
+    last_received: Instant,
+    protocol: Option<Protocol>,
+}
+
+impl Session {
+    pub fn new(
+        reader: impl Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+        peer: SocketAddr,
+    ) -> Self {
+        Session {
+            reader: BufReader::new(Box::new(reader)),
+            writer: Box::new(writer),
+            peer,
+            stream: None,
+            deadline: None,
+            idle_timeout: None,
+            last_received: Instant::now(),
+            protocol: None,
+        }
+    }
+
+    pub fn from_tcp(stream: TcpStream) -> std::io::Result<Self> {
+        let peer = stream.peer_addr()?;
+        Ok(Session {
+            stream: Some(stream.try_clone()?),
+            ..Self::new(stream.try_clone()?, stream, peer)
+        })
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Fail all reads after `deadline`.
+    ///
+    /// For sessions created by `from_tcp` this also applies to reads that are
+    /// already waiting for input, for other sessions it is only checked before
+    /// each read.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Fail reads once nothing was received for `idle_timeout`.
+    ///
+    /// The timeout starts again with every package (or text query) received,
+    /// so long lists whose entries are acknowledged in time are not cut off.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Whether the deadline or idle timeout of this session has passed.
+    pub fn is_expired(&self) -> bool {
+        match self.next_deadline() {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        }
+    }
+
+    /// The earlier of the deadline and the end of the idle timeout.
+    fn next_deadline(&self) -> Option<Instant> {
+        let idle_deadline = self
+            .idle_timeout
+            .map(|idle_timeout| self.last_received + idle_timeout);
+
+        match (self.deadline, idle_deadline) {
+            (Some(deadline), Some(idle_deadline)) => Some(deadline.min(idle_deadline)),
+            (deadline, idle_deadline) => deadline.or(idle_deadline),
+        }
+    }
+
+    fn apply_deadline(&mut self) -> std::io::Result<()> {
+        let timeout = match self.next_deadline() {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(timeout) if timeout.as_nanos() > 0 => Some(timeout),
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "session timed out",
+                    ))
+                }
+            },
+            None => None,
+        };
+
+        if let Some(stream) = &self.stream {
+            stream.set_read_timeout(timeout)?;
+            stream.set_write_timeout(timeout)?;
+        }
+
+        Ok(())
+    }
+
+    /// Determine the protocol the client is speaking, without consuming any input.
+    pub fn protocol(&mut self) -> std::io::Result<Option<Protocol>> {
+        if self.protocol.is_none() {
+            self.apply_deadline()?;
+            self.protocol = sniff_protocol(&mut self.reader)?;
+        }
+        Ok(self.protocol)
+    }
+
+    /// Receive the next package, returning `None` if the client closed the connection.
+    pub fn receive(&mut self) -> std::io::Result<Option<Package<Server>>> {
+        self.apply_deadline()?;
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let package = Package::<Server>::deserialize(&mut self.reader)?;
+        self.last_received = Instant::now();
+        Ok(Some(package))
+    }
+
+    pub fn send(&mut self, package: impl Into<Package<Server>>) -> std::io::Result<()> {
+        // write each package at once, so that it ends up in a single segment
+        let mut buffer = Vec::new();
+        package.into().serialize(&mut buffer)?;
+
+        self.writer.write_all(&buffer)?;
+        self.writer.flush()
+    }
+
+    /// Wait for the `Acknowledge` that follows every entry of a list.
+    pub fn receive_acknowledge(&mut self) -> std::io::Result<()> {
+        match self.receive()? {
+            Some(package) if package.is::<Acknowledge>() => Ok(()),
+            Some(package) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("expected Acknowledge, got {:?}", package),
+            )),
+            None => Err(std::io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    pub fn receive_text_query(&mut self) -> std::io::Result<Option<TextQuery>> {
+        self.apply_deadline()?;
+        let query = TextQuery::read(&mut self.reader)?;
+        self.last_received = Instant::now();
+        Ok(query)
+    }
+
+    pub fn send_text_reply(&mut self, reply: &TextReply) -> std::io::Result<()> {
+        reply.write(&mut self.writer)?;
+        self.writer.flush()
+    }
+}
+
+/// Answer the requests of `session` with `service` until the client disconnects.
+///
+/// Clients speaking the text protocol get to make a single query.
+pub fn serve(service: &impl Service, mut session: Session) -> std::io::Result<()> {
+    service.connected(&mut session)?;
+
+    let result = handle_requests(service, &mut session);
+    // text clients only understand replies to their query, so they are just disconnected
+    if result.is_err() && session.is_expired() && session.protocol != Some(Protocol::Text) {
+        let _ = session.send(Error::from(String::from("session timed out")));
+    }
+
+    service.disconnected(&session);
+
+    result
+}
+
+fn handle_requests(service: &impl Service, session: &mut Session) -> std::io::Result<()> {
+    match session.protocol()? {
+        None => Ok(()),
+        Some(Protocol::Text) => match session.receive_text_query()? {
+            Some(query) => service.text_query(session, query),
+            None => Ok(()),
+        },
+        Some(Protocol::Binary) => {
+            while let Some(request) = session.receive()? {
+                service.call(session, request)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Accept connections on `listener`, serving each one on its own thread, with
+/// at most `MAX_WORKERS` at once.
+pub fn listen<S: Service + 'static>(listener: TcpListener, service: Arc<S>) -> std::io::Result<()> {
+    listen_with_workers(listener, service, MAX_WORKERS)
+}
+
+/// Accept connections on `listener`, serving each one on its own thread.
+///
+/// While `max_workers` connections are being served, new connections wait in
+/// the backlog of `listener`. Only fails if `listener` can not accept at all.
+pub fn listen_with_workers<S: Service + 'static>(
+    listener: TcpListener,
+    service: Arc<S>,
+    max_workers: usize,
+) -> std::io::Result<()> {
+    let workers = Arc::new(Workers::default());
+
+    loop {
+        workers.wait_below(max_workers);
+
+        let (stream, _) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => return Err(err),
+            Err(err) => {
+                service.accept_failed(&err);
+                std::thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+
+        let worker = Worker::start(&workers);
+        let service = service.clone();
+        std::thread::spawn(move || {
+            let _worker = worker;
+            if let Ok(session) = Session::from_tcp(stream) {
+                let _ = serve(&*service, session);
+            }
+        });
+    }
+}
+
+/// The number of connections being served by `listen`.
+#[derive(Default)]
+struct Workers {
+    running: Mutex<usize>,
+    finished: Condvar,
+}
+
+impl Workers {
+    fn running(&self) -> std::sync::MutexGuard<'_, usize> {
+        self.running.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn wait_below(&self, max_workers: usize) {
+        let mut running = self.running();
+        while *running >= max_workers {
+            running = self
+                .finished
+                .wait(running)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+}
+
+/// A connection being served, counted until dropped.
+struct Worker {
+    workers: Arc<Workers>,
+}
+
+impl Worker {
+    fn start(workers: &Arc<Workers>) -> Self {
+        *workers.running() += 1;
+        Worker {
+            workers: workers.clone(),
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        *self.workers.running() -= 1;
+        self.workers.finished.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{Acknowledge, PeerQuery};
+
+    /// Answers every request with an `Acknowledge`.
+    struct Echo;
+
+    impl Service for Echo {
+        fn call(&self, session: &mut Session, _: Package<Server>) -> std::io::Result<()> {
+            session.send(Acknowledge {})
+        }
+    }
+
+    #[test]
+    fn limits_workers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || listen_with_workers(listener, Arc::new(Echo), 1));
+
+        // takes the only worker, without sending anything
+        let first = TcpStream::connect(address).unwrap();
+
+        let mut second = TcpStream::connect(address).unwrap();
+        Package::<Server>::new(PeerQuery {
+            number: 1,
+            version: 1,
+        })
+        .serialize(&mut second)
+        .unwrap();
+
+        second
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        assert!(Package::<Server>::deserialize(&mut second).is_err());
+
+        drop(first);
+        second.set_read_timeout(None).unwrap();
+        let reply = Package::<Server>::deserialize(&mut second).unwrap();
+        assert!(reply.is::<Acknowledge>());
+    }
+}
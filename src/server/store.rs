@@ -0,0 +1,414 @@
+use super::search::SearchIndex;
+use super::{DirectoryTimestamp, PackageBody, PeerReply};
+use crate::wire::{Decode, Encode};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Storage backend for the entries of a directory server.
+///
+/// Keeps the choice of storage out of the protocol code, so that servers can
+/// run on top of any backend and tests can swap in a `MemoryStore`.
+pub trait DirectoryStore {
+    /// Look up the entry for `number`.
+    fn get(&self, number: u32) -> std::io::Result<Option<PeerReply>>;
+
+    /// Insert or replace the entry for `entry.number`, returning the previous entry.
+    fn upsert(&mut self, entry: PeerReply) -> std::io::Result<Option<PeerReply>>;
+
+    /// Remove the entry for `number`, returning it if it existed.
+    fn delete(&mut self, number: u32) -> std::io::Result<Option<PeerReply>>;
+
+    /// All entries with a `timestamp` of at least `timestamp`, ordered by number.
+    fn changed_since(&self, timestamp: DirectoryTimestamp) -> std::io::Result<Vec<PeerReply>>;
+
+    /// All entries matching `pattern` according to `search::matches`, ordered by number.
+    fn search(&self, pattern: &str) -> std::io::Result<Vec<PeerReply>> {
+        Ok(self
+            .changed_since(DirectoryTimestamp::NEVER)?
+            .into_iter()
+            .filter(|entry| super::search::matches(pattern, entry))
+            .collect())
+    }
+}
+
+/// A `DirectoryStore` that only lives in memory.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStore {
+    entries: BTreeMap<u32, PeerReply>,
+    index: SearchIndex,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl DirectoryStore for MemoryStore {
+    fn get(&self, number: u32) -> std::io::Result<Option<PeerReply>> {
+        Ok(self.entries.get(&number).cloned())
+    }
+
+    fn upsert(&mut self, entry: PeerReply) -> std::io::Result<Option<PeerReply>> {
+        self.index.insert(&entry);
+        Ok(self.entries.insert(entry.number, entry))
+    }
+
+    fn delete(&mut self, number: u32) -> std::io::Result<Option<PeerReply>> {
+        self.index.remove(number);
+        Ok(self.entries.remove(&number))
+    }
+
+    fn changed_since(&self, timestamp: DirectoryTimestamp) -> std::io::Result<Vec<PeerReply>> {
+        Ok(self
+            .entries
+            .values()
+            .filter(|entry| entry.timestamp >= timestamp)
+            .cloned()
+            .collect())
+    }
+
+    fn search(&self, pattern: &str) -> std::io::Result<Vec<PeerReply>> {
+        Ok(self
+            .index
+            .search(pattern)
+            .into_iter()
+            .map(|number| self.entries[&number].clone())
+            .collect())
+    }
+}
+
+const RECORD_UPSERT: u8 = 0x01;
+const RECORD_DELETE: u8 = 0x02;
+
+/// A `DirectoryStore` backed by an append-only log file.
+///
+/// Every change is appended to the log and synced to disk before it is
+/// applied in memory. A record that was only partially written (e.g. because
+/// the process crashed) is discarded when the log is opened again.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    log: File,
+    entries: MemoryStore,
+}
+
+impl FileStore {
+    /// Open the log at `path`, creating it if it does not exist yet, and replay it.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut entries = MemoryStore::new();
+        let valid_length = Self::replay(&mut log, &mut entries)?;
+
+        if valid_length < log.metadata()?.len() {
+            // the last record was not written completely
+            log.set_len(valid_length)?;
+            log.sync_all()?;
+        }
+
+        Ok(FileStore { path, log, entries })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Rewrite the log so that it only contains the current entries.
+    pub fn compact(&mut self) -> std::io::Result<()> {
+        let mut temporary_path = self.path.clone().into_os_string();
+        temporary_path.push(".tmp");
+        let temporary_path = PathBuf::from(temporary_path);
+
+        {
+            let mut buffer = Vec::new();
+            for entry in self.entries.changed_since(DirectoryTimestamp::NEVER)? {
+                Self::encode_upsert(&entry, &mut buffer)?;
+            }
+
+            let mut file = File::create(&temporary_path)?;
+            file.write_all(&buffer)?;
+            file.sync_all()?;
+        }
+
+        std::fs::rename(&temporary_path, &self.path)?;
+        Self::sync_directory(&self.path)?;
+
+        self.log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+
+        Ok(())
+    }
+
+    /// Make the rename of the log at `path` durable.
+    #[cfg(unix)]
+    fn sync_directory(path: &Path) -> std::io::Result<()> {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(directory)?.sync_all()
+    }
+
+    /// Directories can not be opened (and don't need to be synced) on other platforms.
+    #[cfg(not(unix))]
+    fn sync_directory(_path: &Path) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Apply all complete records in `log` to `entries`, returning the length
+    /// of the valid part of the log.
+    fn replay(log: &mut File, entries: &mut MemoryStore) -> std::io::Result<u64> {
+        log.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(log);
+        let mut valid_length = 0;
+
+        loop {
+            match Self::read_record(&mut reader) {
+                Ok(Some(Record::Upsert(entry))) => {
+                    entries.upsert(entry)?;
+                }
+                Ok(Some(Record::Delete(number))) => {
+                    entries.delete(number)?;
+                }
+                Ok(None) => return Ok(valid_length),
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(valid_length)
+                }
+                Err(err) => return Err(err),
+            }
+
+            valid_length = reader.stream_position()?;
+        }
+    }
+
+    fn read_record(reader: &mut impl std::io::Read) -> std::io::Result<Option<Record>> {
+        let mut tag = [0u8];
+        loop {
+            match reader.read(&mut tag) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        match tag[0] {
+            RECORD_UPSERT => match PeerReply::deserialize(reader)? {
+                Some(entry) => Ok(Some(Record::Upsert(entry))),
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "log record does not contain a PeerReply",
+                )),
+            },
+            RECORD_DELETE => Ok(Some(Record::Delete(u32::decode(reader)?))),
+            tag => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unknown log record type {:#04x}", tag),
+            )),
+        }
+    }
+
+    fn encode_upsert(entry: &PeerReply, buffer: &mut Vec<u8>) -> std::io::Result<()> {
+        buffer.push(RECORD_UPSERT);
+        PackageBody::serialize(entry, buffer)
+    }
+
+    fn append(&mut self, record: &[u8]) -> std::io::Result<()> {
+        self.log.write_all(record)?;
+        self.log.sync_data()
+    }
+}
+
+enum Record {
+    Upsert(PeerReply),
+    Delete(u32),
+}
+
+impl DirectoryStore for FileStore {
+    fn get(&self, number: u32) -> std::io::Result<Option<PeerReply>> {
+        self.entries.get(number)
+    }
+
+    fn upsert(&mut self, entry: PeerReply) -> std::io::Result<Option<PeerReply>> {
+        let mut record = Vec::new();
+        Self::encode_upsert(&entry, &mut record)?;
+        self.append(&record)?;
+
+        self.entries.upsert(entry)
+    }
+
+    fn delete(&mut self, number: u32) -> std::io::Result<Option<PeerReply>> {
+        if self.entries.get(number)?.is_none() {
+            return Ok(None);
+        }
+
+        let mut record = vec![RECORD_DELETE];
+        number.encode(&mut record)?;
+        self.append(&record)?;
+
+        self.entries.delete(number)
+    }
+
+    fn changed_since(&self, timestamp: DirectoryTimestamp) -> std::io::Result<Vec<PeerReply>> {
+        self.entries.changed_since(timestamp)
+    }
+
+    fn search(&self, pattern: &str) -> std::io::Result<Vec<PeerReply>> {
+        self.entries.search(pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{ClientType, PeerFlags};
+    use crate::Extension;
+    use std::net::Ipv4Addr;
+
+    fn entry(number: u32, name: &str, timestamp: u32) -> PeerReply {
+        let timestamp = DirectoryTimestamp::from_raw(timestamp);
+        PeerReply {
+            number,
+            name: name.into(),
+            flags: PeerFlags::empty(),
+            client_type: ClientType::BaudotHostname,
+            hostname: "example.org".into(),
+            ipaddress: Ipv4Addr::BROADCAST,
+            port: 134,
+            extension: Extension::NONE,
+            pin: 0,
+            timestamp,
+        }
+    }
+
+    fn temporary_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("itelex-store-{}-{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn exercise(store: &mut impl DirectoryStore) {
+        assert_eq!(store.upsert(entry(1, "Alice Example", 10)).unwrap(), None);
+        assert_eq!(store.upsert(entry(2, "Bob", 20)).unwrap(), None);
+        assert_eq!(
+            store.upsert(entry(1, "Alice Example", 30)).unwrap(),
+            Some(entry(1, "Alice Example", 10))
+        );
+
+        assert_eq!(store.get(1).unwrap(), Some(entry(1, "Alice Example", 30)));
+        assert_eq!(store.get(3).unwrap(), None);
+
+        assert_eq!(
+            store.search("alice").unwrap(),
+            vec![entry(1, "Alice Example", 30)]
+        );
+        assert_eq!(
+            store
+                .changed_since(DirectoryTimestamp::from_raw(20))
+                .unwrap(),
+            vec![entry(1, "Alice Example", 30), entry(2, "Bob", 20)]
+        );
+
+        assert_eq!(store.delete(2).unwrap(), Some(entry(2, "Bob", 20)));
+        assert_eq!(store.delete(2).unwrap(), None);
+    }
+
+    #[test]
+    fn memory_store() {
+        exercise(&mut MemoryStore::new());
+    }
+
+    #[test]
+    fn file_store_survives_reopen() {
+        let path = temporary_path("reopen");
+
+        exercise(&mut FileStore::open(&path).unwrap());
+
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(
+            store.changed_since(DirectoryTimestamp::NEVER).unwrap(),
+            vec![entry(1, "Alice Example", 30)]
+        );
+
+        store.compact().unwrap();
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(
+            store.changed_since(DirectoryTimestamp::NEVER).unwrap(),
+            vec![entry(1, "Alice Example", 30)]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_store_discards_partial_record() {
+        let path = temporary_path("partial");
+
+        FileStore::open(&path)
+            .unwrap()
+            .upsert(entry(1, "Alice", 10))
+            .unwrap();
+
+        let complete_length = std::fs::metadata(&path).unwrap().len();
+        {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(&[RECORD_UPSERT, 0x05, 100, 1, 2]).unwrap();
+        }
+
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), complete_length);
+        assert_eq!(
+            store.changed_since(DirectoryTimestamp::NEVER).unwrap(),
+            vec![entry(1, "Alice", 10)]
+        );
+
+        store.upsert(entry(2, "Bob", 20)).unwrap();
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.get(2).unwrap(), Some(entry(2, "Bob", 20)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_record_retries_interrupted_reads() {
+        /// Fails the first read with `Interrupted`.
+        struct Interrupting<'a>(bool, &'a [u8]);
+
+        impl std::io::Read for Interrupting<'_> {
+            fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+                if !std::mem::replace(&mut self.0, true) {
+                    return Err(std::io::ErrorKind::Interrupted.into());
+                }
+                self.1.read(buffer)
+            }
+        }
+
+        let record = [RECORD_DELETE, 7, 0, 0, 0];
+        let mut reader = Interrupting(false, &record);
+        assert!(matches!(
+            FileStore::read_record(&mut reader),
+            Ok(Some(Record::Delete(7)))
+        ));
+        assert!(matches!(FileStore::read_record(&mut reader), Ok(None)));
+    }
+}
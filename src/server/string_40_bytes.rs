@@ -1,4 +1,7 @@
-#[derive(Debug, Eq, PartialEq, Clone)]
+use crate::io;
+use crate::wire::{Decode, Encode};
+
+#[derive(Debug, Default, Eq, PartialEq, Clone)]
 #[cfg_attr(feature = "serde_serialize", derive(serde::Serialize))]
 #[cfg_attr(feature = "serde_deserialize", derive(serde::Deserialize))]
 pub struct String40Bytes(pub String);
@@ -14,14 +17,15 @@ impl From<&str> for String40Bytes {
     }
 }
 
-impl<W> binserde::Serialize<W> for String40Bytes
-where
-    W: std::io::Write,
-{
-    fn serialize_ne(&self, writer: &mut W) -> std::result::Result<(), std::io::Error> {
-        let mut string = self.0.clone().into_bytes();
+impl Encode for String40Bytes {
+    fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        // remove all content that will not fit into the buffer, without splitting a character
+        let mut end = self.0.len().min(39);
+        while !self.0.is_char_boundary(end) {
+            end -= 1;
+        }
 
-        string.truncate(39); // remove all content that will not fit into the buffer
+        let mut string = self.0.as_bytes()[..end].to_vec();
         string.resize_with(40, || 0); // extend the string to fit the buffer, padding with zeros
         writer.write_all(&string)?; // write the string to the buffer
 
@@ -29,19 +33,13 @@ where
     }
 }
 
-impl<R> binserde::Deserialize<R> for String40Bytes
-where
-    R: std::io::Read,
-{
-    fn deserialize_ne(reader: &mut R) -> std::io::Result<Self> {
+impl Decode for String40Bytes {
+    fn decode(reader: &mut impl io::Read) -> io::Result<Self> {
         let mut buffer = [0u8; 40];
 
         reader.read_exact(&mut buffer)?;
 
-        let end_of_content = buffer
-            .iter()
-            .position(|x| *x == 0)
-            .unwrap_or_else(|| buffer.len());
+        let end_of_content = buffer.iter().position(|x| *x == 0).unwrap_or(buffer.len());
 
         let string = String::from_utf8_lossy(&buffer[0..end_of_content]).into();
 
@@ -66,8 +64,3 @@ impl std::ops::DerefMut for String40Bytes {
         &mut self.0
     }
 }
-impl Default for String40Bytes {
-    fn default() -> Self {
-        Self(String::new())
-    }
-}
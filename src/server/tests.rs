@@ -1,6 +1,10 @@
-use super::{packages::*, ClientType, Package, Server};
+use super::{
+    packages::*, ClientType, ClientTypeError, DirectoryTimestamp, Package, PeerAddress, PeerFlags,
+    Server, String40Bytes, ValidationProblem,
+};
+use crate::Extension;
 use std::io::Cursor;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddr};
 
 fn test_all<P: super::PackageBody<Class = Server>>(package: P, serialized: Vec<u8>) {
     {
@@ -152,14 +156,14 @@ fn type_5() {
     let package = PeerReply {
         number: 0x01_02_03_04,
         name: String::from("Test").into(),
-        flags: PeerReply::flags(true),
+        flags: PeerFlags::DISABLED,
         client_type: ClientType::BaudotDynIp,
         hostname: String::from("host.name").into(),
         ipaddress: Ipv4Addr::from(0x08_09_0a_0b),
         port: 0x0c_0d,
-        extension: 0x0e,
+        extension: Extension::from_wire(0x0e),
         pin: 0x0f_10,
-        timestamp: 0x11_12_13_14,
+        timestamp: DirectoryTimestamp::from_raw(0x11_12_13_14),
     };
 
     test_all(package, serialized);
@@ -246,3 +250,176 @@ fn type_255() {
 
     test_all(package, serialized);
 }
+
+fn valid_peer_reply() -> PeerReply {
+    PeerReply {
+        number: 1234,
+        name: String::from("Test").into(),
+        flags: PeerFlags::empty(),
+        client_type: ClientType::BaudotHostname,
+        hostname: String::from("host.name").into(),
+        ipaddress: Ipv4Addr::BROADCAST,
+        port: 134,
+        extension: Extension::NONE,
+        pin: 0,
+        timestamp: DirectoryTimestamp::from_raw(1000),
+    }
+}
+
+#[test]
+fn validate_valid_peer_reply() {
+    assert_eq!(
+        valid_peer_reply().validate_at(DirectoryTimestamp::from_raw(1000)),
+        vec![]
+    );
+}
+
+#[test]
+fn validate_invalid_peer_reply() {
+    let package = PeerReply {
+        name: String::from("a name that is far too long to fit into 40 bytes").into(),
+        client_type: ClientType::AsciiIpaddress,
+        port: 0,
+        extension: Extension::from_wire(111),
+        timestamp: DirectoryTimestamp::from_raw(2000),
+        ..valid_peer_reply()
+    };
+
+    assert_eq!(
+        package.validate_at(DirectoryTimestamp::from_raw(1000)),
+        vec![
+            ValidationProblem::InvalidExtension(111),
+            ValidationProblem::MissingIpaddress,
+            ValidationProblem::InvalidPort,
+            ValidationProblem::NameTooLong(48),
+            ValidationProblem::TimestampInFuture(DirectoryTimestamp::from_raw(2000)),
+        ]
+    );
+}
+
+#[test]
+fn peer_flags_keep_unknown_bits() {
+    let serialized: Vec<u8> = {
+        let mut package = valid_peer_reply();
+        package.flags = PeerFlags::from_bits(0x8001);
+
+        let mut buffer = Vec::new();
+        Package::<Server>::new(package)
+            .serialize(&mut buffer)
+            .unwrap();
+        buffer
+    };
+
+    let mut package = Package::<Server>::deserialize(&mut Cursor::new(serialized))
+        .unwrap()
+        .downcast::<PeerReply>()
+        .unwrap();
+    assert_eq!(package.flags.bits(), 0x8001);
+    assert!(!package.disabled());
+
+    package.flags.set_disabled(true);
+    assert_eq!(package.flags.bits(), 0x8003);
+    assert_eq!(package.flags.unknown_bits(), 0x8001);
+
+    package.flags.set_disabled(false);
+    assert_eq!(package.flags, PeerFlags::from_bits(0x8001));
+}
+
+#[test]
+fn client_type_wire() {
+    use crate::wire::{Decode, Encode};
+
+    let mut buffer = Vec::new();
+    ClientType::BaudotDynIp.encode(&mut buffer).unwrap();
+    ClientType::Email.encode(&mut buffer).unwrap();
+    assert_eq!(buffer, vec![5, 6]);
+
+    // not a client type
+    buffer.push(7);
+
+    let mut reader = &buffer[..];
+    assert_eq!(
+        ClientType::decode(&mut reader).unwrap(),
+        ClientType::BaudotDynIp
+    );
+    assert_eq!(ClientType::decode(&mut reader).unwrap(), ClientType::Email);
+    assert!(ClientType::decode(&mut reader).is_err());
+}
+
+#[test]
+fn client_type_errors() {
+    use std::convert::TryFrom;
+
+    assert_eq!(
+        ClientType::try_from(7),
+        Err(ClientTypeError::InvalidWireValue(7))
+    );
+    assert_eq!(
+        ClientType::try_from(7).unwrap_err().to_string(),
+        "7 is not a valid client type"
+    );
+    assert_eq!("email".parse::<ClientType>(), Ok(ClientType::Email));
+    assert_eq!("5".parse::<ClientType>(), Ok(ClientType::BaudotDynIp));
+    assert_eq!(
+        "7".parse::<ClientType>(),
+        Err(ClientTypeError::InvalidString("7".into()))
+    );
+    assert_eq!(
+        "telex".parse::<ClientType>().unwrap_err().to_string(),
+        "\"telex\" is not a valid client type"
+    );
+}
+
+#[test]
+fn string_40_bytes_truncates_at_char_boundary() {
+    use crate::wire::{Decode, Encode};
+
+    // 38 bytes and a two byte character, which does not fit before the terminator
+    let string = String40Bytes::from(format!("{}ä", "a".repeat(38)));
+
+    let mut buffer = Vec::new();
+    string.encode(&mut buffer).unwrap();
+    assert_eq!(buffer.len(), 40);
+    assert_eq!(buffer[38..], [0, 0]);
+
+    let string = String40Bytes::decode(&mut &buffer[..]).unwrap();
+    assert_eq!(string.0, "a".repeat(38));
+}
+
+#[test]
+fn peer_reply_address() {
+    let resolver = |hostname: &str, port| {
+        assert_eq!(hostname, "host.name");
+        Ok(vec![SocketAddr::from(([192, 0, 2, 1], port))])
+    };
+
+    let package = valid_peer_reply();
+    assert_eq!(
+        package.address(),
+        PeerAddress::Hostname {
+            hostname: String::from("host.name"),
+            port: 134
+        }
+    );
+    assert_eq!(
+        package.to_socket_addrs(&resolver).unwrap(),
+        vec![SocketAddr::from(([192, 0, 2, 1], 134))]
+    );
+
+    let package = PeerReply {
+        client_type: ClientType::BaudotDynIp,
+        ipaddress: Ipv4Addr::new(198, 51, 100, 7),
+        ..valid_peer_reply()
+    };
+    assert_eq!(
+        package.to_socket_addrs(&resolver).unwrap(),
+        vec![SocketAddr::from(([198, 51, 100, 7], 134))]
+    );
+
+    let package = PeerReply {
+        client_type: ClientType::Deleted,
+        ..valid_peer_reply()
+    };
+    assert_eq!(package.address(), PeerAddress::Deleted);
+    assert!(package.to_socket_addrs(&resolver).is_err());
+}
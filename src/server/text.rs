@@ -0,0 +1,336 @@
+//! The line based text protocol spoken on the directory port besides the binary
+//! `Package<Server>`s.
+//!
+//! A client sends a single line consisting of `q` followed by a number and
+//! receives either
+//! ```text
+//! ok
+//! <number>
+//! <name>
+//! <client type>
+//! <hostname or ip address>
+//! <port>
+//! <extension>
+//! +++
+//! ```
+//! or
+//! ```text
+//! fail
+//! <number>
+//! unknown
+//! +++
+//! ```
+//! with every line terminated by `\r\n`.
+
+use super::{ClientType, PeerAddress, PeerReply, Server};
+use crate::Extension;
+use std::convert::TryFrom;
+use std::io::{BufRead, Read, Write};
+
+/// Queries are far shorter than this, anything longer is not a query.
+const MAX_LINE_LENGTH: u64 = 128;
+
+const END_OF_REPLY: &str = "+++";
+
+/// The lines of the longest reply, without the `END_OF_REPLY`.
+const MAX_REPLY_LINES: usize = 7;
+
+/// The protocol a client on the directory port is speaking.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Protocol {
+    /// `Package<Server>`s
+    Binary,
+    /// `TextQuery`s
+    Text,
+}
+
+impl Protocol {
+    /// Determine the protocol from the first byte a client sent.
+    pub fn from_first_byte(byte: u8) -> Option<Self> {
+        if Server::try_from(byte).is_ok() {
+            Some(Protocol::Binary)
+        } else if byte.is_ascii_alphabetic() {
+            Some(Protocol::Text)
+        } else {
+            None
+        }
+    }
+}
+
+/// Determine the protocol of a client without consuming any input.
+///
+/// Returns `None` if the client closed the connection before sending anything.
+pub fn sniff_protocol(reader: &mut impl BufRead) -> std::io::Result<Option<Protocol>> {
+    let first_byte = match reader.fill_buf()?.first() {
+        Some(byte) => *byte,
+        None => return Ok(None),
+    };
+
+    Protocol::from_first_byte(first_byte)
+        .map(Some)
+        .ok_or_else(|| invalid_data(format!("unknown protocol (first byte {:#04x})", first_byte)))
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum TextQuery {
+    /// `q<number>`: look up the entry for a number
+    Query(u32),
+}
+
+impl TextQuery {
+    pub fn parse(line: &str) -> std::io::Result<Self> {
+        let line = line.trim();
+
+        let mut chars = line.chars();
+        match chars.next() {
+            Some('q') | Some('Q') => {
+                let number = chars.as_str().trim();
+                number
+                    .parse()
+                    .map(TextQuery::Query)
+                    .map_err(|_| invalid_data(format!("invalid number in query {:?}", line)))
+            }
+            _ => Err(invalid_data(format!("unknown query {:?}", line))),
+        }
+    }
+
+    /// Read a single query line, returning `None` if the client closed the connection.
+    pub fn read(reader: &mut impl BufRead) -> std::io::Result<Option<Self>> {
+        let mut line = String::new();
+        reader.take(MAX_LINE_LENGTH).read_line(&mut line)?;
+
+        if line.is_empty() {
+            Ok(None)
+        } else {
+            Self::parse(&line).map(Some)
+        }
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            TextQuery::Query(number) => write!(writer, "q{}\r\n", number),
+        }
+    }
+}
+
+/// The fields of a `PeerReply` that are part of a text reply.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct TextRecord {
+    pub number: u32,
+    pub name: String,
+    pub client_type: ClientType,
+    /// the hostname or ip address, depending on the `client_type`
+    pub address: String,
+    pub port: u16,
+    pub extension: Extension,
+}
+
+impl From<&PeerReply> for TextRecord {
+    fn from(entry: &PeerReply) -> Self {
+        TextRecord {
+            number: entry.number,
+            name: entry.name.0.clone(),
+            client_type: entry.client_type,
+            address: match entry.address() {
+                PeerAddress::Hostname { hostname, .. } => hostname,
+                PeerAddress::Ip(address) => address.ip().to_string(),
+                PeerAddress::Email(address) => address,
+                PeerAddress::Deleted | PeerAddress::Unknown => String::new(),
+            },
+            port: entry.port,
+            extension: entry.extension,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum TextReply {
+    Found(TextRecord),
+    NotFound(u32),
+}
+
+impl TextReply {
+    /// The reply to a query for `number` that found `entry`.
+    pub fn new(number: u32, entry: Option<&PeerReply>) -> Self {
+        match entry {
+            Some(entry) => TextReply::Found(entry.into()),
+            None => TextReply::NotFound(number),
+        }
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            TextReply::Found(record) => write!(
+                writer,
+                "ok\r\n{}\r\n{}\r\n{}\r\n{}\r\n{}\r\n{}\r\n{}\r\n",
+                record.number,
+                record.name,
+                record.client_type,
+                record.address,
+                record.port,
+                record.extension,
+                END_OF_REPLY,
+            ),
+            TextReply::NotFound(number) => {
+                write!(
+                    writer,
+                    "fail\r\n{}\r\nunknown\r\n{}\r\n",
+                    number, END_OF_REPLY
+                )
+            }
+        }
+    }
+
+    pub fn read(reader: &mut impl BufRead) -> std::io::Result<Self> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.take(MAX_LINE_LENGTH).read_line(&mut line)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+
+            let line = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+            if line == END_OF_REPLY {
+                break;
+            }
+            if lines.len() == MAX_REPLY_LINES {
+                return Err(invalid_data(format!(
+                    "text reply is longer than {} lines",
+                    MAX_REPLY_LINES
+                )));
+            }
+            lines.push(line);
+        }
+
+        Self::parse(&lines)
+    }
+
+    fn parse(lines: &[String]) -> std::io::Result<Self> {
+        fn field<T: std::str::FromStr>(value: &str, name: &str) -> std::io::Result<T> {
+            value
+                .parse()
+                .map_err(|_| invalid_data(format!("invalid {} {:?} in text reply", name, value)))
+        }
+
+        match lines {
+            [status, number, ..] if status == "fail" => {
+                Ok(TextReply::NotFound(field(number, "number")?))
+            }
+            [status, number, name, client_type, address, port, extension] if status == "ok" => {
+                Ok(TextReply::Found(TextRecord {
+                    number: field(number, "number")?,
+                    name: name.clone(),
+                    client_type: ClientType::try_from(field::<u8>(client_type, "client type")?)
+                        .map_err(|err| invalid_data(err.to_string()))?,
+                    address: address.clone(),
+                    port: field(port, "port")?,
+                    extension: field(extension, "extension")?,
+                }))
+            }
+            _ => Err(invalid_data(format!("malformed text reply {:?}", lines))),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{DirectoryTimestamp, PeerFlags};
+    use std::io::Cursor;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn sniff() {
+        let mut binary = Cursor::new(vec![0x03, 5, 1, 0, 0, 0, 1]);
+        assert_eq!(sniff_protocol(&mut binary).unwrap(), Some(Protocol::Binary));
+        assert_eq!(binary.position(), 0);
+
+        let mut text = Cursor::new(b"q1234\r\n".to_vec());
+        assert_eq!(sniff_protocol(&mut text).unwrap(), Some(Protocol::Text));
+        assert_eq!(
+            TextQuery::read(&mut text).unwrap(),
+            Some(TextQuery::Query(1234))
+        );
+
+        assert_eq!(sniff_protocol(&mut Cursor::new(vec![])).unwrap(), None);
+        assert!(sniff_protocol(&mut Cursor::new(vec![0x80])).is_err());
+    }
+
+    #[test]
+    fn parse_query() {
+        assert_eq!(TextQuery::parse("q 42\n").unwrap(), TextQuery::Query(42));
+        assert!(TextQuery::parse("q").is_err());
+        assert!(TextQuery::parse("x42").is_err());
+    }
+
+    #[test]
+    fn reply_round_trip() {
+        let entry = PeerReply {
+            number: 1234,
+            name: "Test".into(),
+            flags: PeerFlags::empty(),
+            client_type: ClientType::AsciiIpaddress,
+            hostname: "".into(),
+            ipaddress: Ipv4Addr::new(10, 0, 0, 1),
+            port: 134,
+            extension: Extension::from_wire(105),
+            pin: 0,
+            timestamp: DirectoryTimestamp::NEVER,
+        };
+
+        let mut buffer = Vec::new();
+        TextReply::new(1234, Some(&entry))
+            .write(&mut buffer)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer.clone()).unwrap(),
+            "ok\r\n1234\r\nTest\r\n4\r\n10.0.0.1\r\n134\r\n5\r\n+++\r\n"
+        );
+        assert_eq!(
+            TextReply::read(&mut Cursor::new(buffer)).unwrap(),
+            TextReply::new(1234, Some(&entry))
+        );
+
+        let mut buffer = Vec::new();
+        TextReply::new(99, None).write(&mut buffer).unwrap();
+        assert_eq!(
+            TextReply::read(&mut Cursor::new(buffer)).unwrap(),
+            TextReply::NotFound(99)
+        );
+    }
+
+    #[test]
+    fn record_address_by_client_type() {
+        let mut entry = PeerReply {
+            number: 1234,
+            name: "Test".into(),
+            flags: PeerFlags::empty(),
+            client_type: ClientType::AsciiIpaddress,
+            // left over from when the entry used a hostname
+            hostname: "old.host.name".into(),
+            ipaddress: Ipv4Addr::new(10, 0, 0, 1),
+            port: 134,
+            extension: Extension::NONE,
+            pin: 0,
+            timestamp: DirectoryTimestamp::NEVER,
+        };
+        assert_eq!(TextRecord::from(&entry).address, "10.0.0.1");
+
+        entry.client_type = ClientType::BaudotHostname;
+        assert_eq!(TextRecord::from(&entry).address, "old.host.name");
+
+        entry.client_type = ClientType::Deleted;
+        assert_eq!(TextRecord::from(&entry).address, "");
+    }
+
+    #[test]
+    fn reply_line_limit() {
+        let endless = b"ok\r\n".repeat(100);
+        let err = TextReply::read(&mut Cursor::new(endless)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::io;
+use crate::wire::{Decode, Encode};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds between 1900-01-01, the epoch of `DirectoryTimestamp`, and 1970-01-01.
+const SECONDS_FROM_1900_TO_1970: u64 = 2_208_988_800;
+
+/// Raw values below this lie after the wrap on 2036-02-07.
+///
+/// It corresponds to 1968-01-20, long before any directory entry was written.
+const ERA_PIVOT: u32 = 0x8000_0000;
+
+/// The `timestamp` of a `PeerReply`: seconds since 1900-01-01 00:00:00 UTC.
+///
+/// The 32 bit counter wraps on 2036-02-07 06:28:16 UTC. Raw values below
+/// `0x8000_0000` (1968-01-20) are therefore interpreted as lying after the
+/// wrap, which makes timestamps up to 2104 representable and ordered
+/// correctly. The raw value `0` is the exception: it is used for entries that
+/// were never updated and always means 1900-01-01.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, Default)]
+pub struct DirectoryTimestamp(u32);
+
+impl DirectoryTimestamp {
+    /// The earliest timestamp, used for entries that were never updated.
+    pub const NEVER: DirectoryTimestamp = DirectoryTimestamp(0);
+
+    pub const fn from_raw(raw: u32) -> Self {
+        DirectoryTimestamp(raw)
+    }
+
+    pub const fn raw(self) -> u32 {
+        self.0
+    }
+
+    /// The current time, clamped to the range of timestamps if the system
+    /// clock is far off.
+    pub fn now() -> Self {
+        Self::saturating_from(SystemTime::now())
+    }
+
+    /// The timestamp of `time`, or the earliest or latest timestamp after
+    /// `NEVER` if `time` lies outside of their range.
+    pub fn saturating_from(time: SystemTime) -> Self {
+        Self::try_from(time).unwrap_or(if time < UNIX_EPOCH {
+            DirectoryTimestamp(ERA_PIVOT)
+        } else {
+            DirectoryTimestamp(ERA_PIVOT - 1)
+        })
+    }
+
+    /// The number of seconds since 1900-01-01, taking the 2036 wrap into account.
+    pub fn seconds_since_1900(self) -> u64 {
+        if self.0 == 0 || self.0 >= ERA_PIVOT {
+            u64::from(self.0)
+        } else {
+            u64::from(self.0) + (1 << 32)
+        }
+    }
+
+    /// The timestamp `seconds` after 1900-01-01, if it is representable.
+    pub fn from_seconds_since_1900(seconds: u64) -> Option<Self> {
+        if seconds == 0 {
+            Some(Self::NEVER)
+        } else if seconds >= u64::from(ERA_PIVOT) && seconds < u64::from(ERA_PIVOT) + (1 << 32) {
+            Some(DirectoryTimestamp(seconds as u32))
+        } else {
+            None
+        }
+    }
+
+    pub fn to_system_time(self) -> SystemTime {
+        let seconds = self.seconds_since_1900();
+
+        if seconds >= SECONDS_FROM_1900_TO_1970 {
+            UNIX_EPOCH + Duration::from_secs(seconds - SECONDS_FROM_1900_TO_1970)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(SECONDS_FROM_1900_TO_1970 - seconds)
+        }
+    }
+}
+
+/// The error returned when converting a `SystemTime` that lies outside of
+/// the range of `DirectoryTimestamp`.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct TimestampOutOfRange;
+
+impl std::fmt::Display for TimestampOutOfRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "time is not representable as a directory timestamp")
+    }
+}
+
+impl std::error::Error for TimestampOutOfRange {}
+
+impl TryFrom<SystemTime> for DirectoryTimestamp {
+    type Error = TimestampOutOfRange;
+
+    fn try_from(time: SystemTime) -> Result<Self, Self::Error> {
+        let seconds = match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => SECONDS_FROM_1900_TO_1970.checked_add(duration.as_secs()),
+            Err(err) => SECONDS_FROM_1900_TO_1970.checked_sub(err.duration().as_secs()),
+        };
+
+        seconds
+            .and_then(Self::from_seconds_since_1900)
+            .ok_or(TimestampOutOfRange)
+    }
+}
+
+impl From<DirectoryTimestamp> for SystemTime {
+    fn from(timestamp: DirectoryTimestamp) -> SystemTime {
+        timestamp.to_system_time()
+    }
+}
+
+/// Formats the timestamp as ISO 8601 in UTC, e.g. `2020-09-13T12:26:40Z`.
+impl std::fmt::Display for DirectoryTimestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.seconds_since_1900() as i64 - SECONDS_FROM_1900_TO_1970 as i64;
+        let (days, seconds) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+        let (year, month, day) = civil_from_days(days);
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParseTimestampError(String);
+
+impl std::fmt::Display for ParseTimestampError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid timestamp {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseTimestampError {}
+
+/// Parses timestamps in the format produced by `Display`.
+impl std::str::FromStr for DirectoryTimestamp {
+    type Err = ParseTimestampError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let error = || ParseTimestampError(string.into());
+
+        let bytes = string.as_bytes();
+        if bytes.len() != 20
+            || bytes[4] != b'-'
+            || bytes[7] != b'-'
+            || bytes[10] != b'T'
+            || bytes[13] != b':'
+            || bytes[16] != b':'
+            || bytes[19] != b'Z'
+        {
+            return Err(error());
+        }
+
+        let field = |range: std::ops::Range<usize>| -> Result<i64, ParseTimestampError> {
+            let digits = &string[range];
+            if digits.bytes().all(|byte| byte.is_ascii_digit()) {
+                digits.parse().map_err(|_| error())
+            } else {
+                Err(error())
+            }
+        };
+
+        let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+        let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+        if !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 59
+        {
+            return Err(error());
+        }
+
+        // dates like 02-31 would silently turn into one in the next month
+        let days = days_from_civil(year, month, day);
+        if civil_from_days(days) != (year, month, day) {
+            return Err(error());
+        }
+
+        let seconds =
+            days * 86400 + hour * 3600 + minute * 60 + second + SECONDS_FROM_1900_TO_1970 as i64;
+
+        if seconds < 0 {
+            return Err(error());
+        }
+        Self::from_seconds_since_1900(seconds as u64).ok_or_else(error)
+    }
+}
+
+/// The number of days between 1970-01-01 and the given date of the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+impl Ord for DirectoryTimestamp {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.seconds_since_1900().cmp(&other.seconds_since_1900())
+    }
+}
+
+impl PartialOrd for DirectoryTimestamp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Encode for DirectoryTimestamp {
+    fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        self.0.encode(writer)
+    }
+}
+
+impl Decode for DirectoryTimestamp {
+    fn decode(reader: &mut impl io::Read) -> io::Result<Self> {
+        u32::decode(reader).map(DirectoryTimestamp)
+    }
+}
+
+#[cfg(feature = "serde_serialize")]
+impl serde::Serialize for DirectoryTimestamp {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u32(self.0)
+    }
+}
+
+#[cfg(feature = "serde_deserialize")]
+impl<'de> serde::Deserialize<'de> for DirectoryTimestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        u32::deserialize(deserializer).map(DirectoryTimestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_time_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let timestamp = DirectoryTimestamp::try_from(time).unwrap();
+
+        assert_eq!(timestamp.raw(), 3_808_988_800);
+        assert_eq!(SystemTime::from(timestamp), time);
+    }
+
+    #[test]
+    fn wraps_in_2036() {
+        // 2040-01-01 00:00:00 UTC
+        let time = UNIX_EPOCH + Duration::from_secs(2_208_988_800);
+        let timestamp = DirectoryTimestamp::try_from(time).unwrap();
+
+        assert_eq!(timestamp.raw(), 123_010_304);
+        assert_eq!(timestamp.to_system_time(), time);
+        assert!(timestamp > DirectoryTimestamp::from_raw(u32::MAX));
+        assert!(DirectoryTimestamp::NEVER < DirectoryTimestamp::from_raw(ERA_PIVOT));
+    }
+
+    #[test]
+    fn iso_8601() {
+        let timestamp = DirectoryTimestamp::from_raw(3_808_988_800);
+        assert_eq!(timestamp.to_string(), "2020-09-13T12:26:40Z");
+        assert_eq!("2020-09-13T12:26:40Z".parse(), Ok(timestamp));
+
+        let after_wrap = DirectoryTimestamp::from_raw(123_010_304);
+        assert_eq!(after_wrap.to_string(), "2040-01-01T00:00:00Z");
+        assert_eq!("2040-01-01T00:00:00Z".parse(), Ok(after_wrap));
+
+        assert_eq!(
+            DirectoryTimestamp::NEVER.to_string(),
+            "1900-01-01T00:00:00Z"
+        );
+        assert_eq!(
+            "1900-01-01T00:00:00Z".parse(),
+            Ok(DirectoryTimestamp::NEVER)
+        );
+
+        assert!("2020-13-01T00:00:00Z"
+            .parse::<DirectoryTimestamp>()
+            .is_err());
+        assert!("2020-09-13 12:26:40".parse::<DirectoryTimestamp>().is_err());
+        assert!("2020-02-31T00:00:00Z"
+            .parse::<DirectoryTimestamp>()
+            .is_err());
+        assert!("2021-02-29T00:00:00Z"
+            .parse::<DirectoryTimestamp>()
+            .is_err());
+        assert!("2020-02-29T00:00:00Z".parse::<DirectoryTimestamp>().is_ok());
+        assert!("1950-01-01T00:00:00Z"
+            .parse::<DirectoryTimestamp>()
+            .is_err());
+    }
+
+    #[test]
+    fn out_of_range() {
+        let before_pivot = UNIX_EPOCH - Duration::from_secs(3 * 365 * 24 * 60 * 60);
+        assert_eq!(
+            DirectoryTimestamp::try_from(before_pivot),
+            Err(TimestampOutOfRange)
+        );
+
+        assert_eq!(
+            DirectoryTimestamp::from_seconds_since_1900(u64::from(ERA_PIVOT) + (1 << 32)),
+            None
+        );
+
+        assert_eq!(
+            DirectoryTimestamp::saturating_from(before_pivot).raw(),
+            ERA_PIVOT
+        );
+        let after_range = UNIX_EPOCH + Duration::from_secs(200 * 365 * 24 * 60 * 60);
+        assert_eq!(
+            DirectoryTimestamp::saturating_from(after_range).raw(),
+            ERA_PIVOT - 1
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use super::{ClientType, DirectoryTimestamp, PeerReply};
+
+/// How many seconds a timestamp may lie in the future before it is considered invalid.
+const ALLOWED_CLOCK_SKEW: u64 = 5 * 60;
+
+/// The longest string that fits into a `String40Bytes`.
+const MAX_STRING_LENGTH: usize = 39;
+
+/// A problem found by `PeerReply::validate`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ValidationProblem {
+    /// `extension` is not a valid `Extension`, contains the wire value
+    InvalidExtension(u8),
+    /// the `client_type` uses a hostname, but `hostname` is empty
+    MissingHostname,
+    /// the `client_type` uses an ip address, but `ipaddress` is the broadcast address
+    MissingIpaddress,
+    /// the entry can be reached, but `port` is 0
+    InvalidPort,
+    /// `name` is longer than 39 bytes and will be truncated on the wire
+    NameTooLong(usize),
+    /// `hostname` is longer than 39 bytes and will be truncated on the wire
+    HostnameTooLong(usize),
+    /// `timestamp` lies in the future
+    TimestampInFuture(DirectoryTimestamp),
+}
+
+impl std::fmt::Display for ValidationProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationProblem::InvalidExtension(extension) => {
+                write!(f, "invalid extension {}", extension)
+            }
+            ValidationProblem::MissingHostname => write!(f, "hostname is missing"),
+            ValidationProblem::MissingIpaddress => write!(f, "ip address is missing"),
+            ValidationProblem::InvalidPort => write!(f, "port is 0"),
+            ValidationProblem::NameTooLong(length) => {
+                write!(
+                    f,
+                    "name is {} bytes long (at most {})",
+                    length, MAX_STRING_LENGTH
+                )
+            }
+            ValidationProblem::HostnameTooLong(length) => write!(
+                f,
+                "hostname is {} bytes long (at most {})",
+                length, MAX_STRING_LENGTH
+            ),
+            ValidationProblem::TimestampInFuture(timestamp) => {
+                write!(f, "timestamp {} lies in the future", timestamp.raw())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationProblem {}
+
+impl PeerReply {
+    /// Check whether this entry is consistent, returning all problems found.
+    pub fn validate(&self) -> Vec<ValidationProblem> {
+        self.validate_at(DirectoryTimestamp::now())
+    }
+
+    /// Like `validate`, with `now` as the current time.
+    pub fn validate_at(&self, now: DirectoryTimestamp) -> Vec<ValidationProblem> {
+        let mut problems = Vec::new();
+
+        if !self.extension.is_valid() {
+            problems.push(ValidationProblem::InvalidExtension(
+                self.extension.to_wire(),
+            ));
+        }
+
+        if self.client_type.uses_hostname() && self.hostname().is_none() {
+            problems.push(ValidationProblem::MissingHostname);
+        }
+
+        if self.client_type.uses_ipaddress() && self.ipaddress().is_none() {
+            problems.push(ValidationProblem::MissingIpaddress);
+        }
+
+        let reachable = match self.client_type {
+            ClientType::Deleted | ClientType::Email => false,
+            ClientType::BaudotDynIp => self.ipaddress().is_some(),
+            _ => true,
+        };
+        if reachable && self.port == 0 {
+            problems.push(ValidationProblem::InvalidPort);
+        }
+
+        if self.name.len() > MAX_STRING_LENGTH {
+            problems.push(ValidationProblem::NameTooLong(self.name.len()));
+        }
+
+        if self.hostname.len() > MAX_STRING_LENGTH {
+            problems.push(ValidationProblem::HostnameTooLong(self.hostname.len()));
+        }
+
+        if self.timestamp.seconds_since_1900() > now.seconds_since_1900() + ALLOWED_CLOCK_SKEW {
+            problems.push(ValidationProblem::TimestampInFuture(self.timestamp));
+        }
+
+        problems
+    }
+}
@@ -0,0 +1,65 @@
+use super::{Error, Server};
+use std::convert::TryFrom;
+
+/// A version of the directory protocol, as carried in the `version` field of
+/// `PeerQuery`, `PeerSearch`, `FullQuery` and `Login`.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+pub enum ProtocolVersion {
+    V1 = 1,
+}
+
+impl ProtocolVersion {
+    /// The version used by this crate when sending requests.
+    pub const CURRENT: ProtocolVersion = ProtocolVersion::V1;
+
+    /// Whether requests of type `package_type` can be made with this version.
+    pub fn supports(self, package_type: Server) -> bool {
+        match self {
+            ProtocolVersion::V1 => matches!(
+                package_type,
+                Server::PeerQuery | Server::FullQuery | Server::Login | Server::PeerSearch
+            ),
+        }
+    }
+
+    /// The version of a request of type `package_type`, or the `Error` to
+    /// reply with if it is not supported.
+    pub fn negotiate(version: u8, package_type: Server) -> Result<Self, Error> {
+        let protocol_version = Self::try_from(version)?;
+
+        if protocol_version.supports(package_type) {
+            Ok(protocol_version)
+        } else {
+            Err(Error::from(format!(
+                "package type {:#04x} is not supported in protocol version {}",
+                package_type as u8, version
+            )))
+        }
+    }
+}
+
+impl TryFrom<u8> for ProtocolVersion {
+    type Error = Error;
+
+    fn try_from(version: u8) -> Result<Self, Self::Error> {
+        match version {
+            1 => Ok(ProtocolVersion::V1),
+            _ => Err(Error::from(format!(
+                "unsupported protocol version {}",
+                version
+            ))),
+        }
+    }
+}
+
+impl From<ProtocolVersion> for u8 {
+    fn from(version: ProtocolVersion) -> u8 {
+        version as u8
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", *self as u8)
+    }
+}
@@ -0,0 +1,225 @@
+//! `proptest` strategies for all packages and their fields.
+//!
+//! Every package body, the fields with wire constraints and `Package`s of each
+//! class implement `Arbitrary`, so `any::<PeerReply>()` or
+//! `any::<Package<Server>>()` generate values that survive a round trip over
+//! the wire unchanged.
+
+use proptest::prelude::*;
+
+#[cfg(feature = "centralex")]
+use crate::centralex::{self, Centralex};
+#[cfg(feature = "client")]
+use crate::client::{self, Client};
+#[cfg(feature = "server")]
+use crate::server::{self, ClientType, DirectoryTimestamp, PeerFlags, Server, String40Bytes};
+#[cfg(any(feature = "server", feature = "client"))]
+use crate::Extension;
+#[cfg(any(feature = "server", feature = "centralex", feature = "client"))]
+use crate::Package;
+
+/// Strings of at most `max_bytes` bytes of UTF-8 without any NUL bytes, as
+/// they are terminated by one on the wire.
+pub fn string(max_bytes: usize) -> impl Strategy<Value = String> {
+    proptest::collection::vec(any::<char>(), 0..=max_bytes).prop_map(move |chars| {
+        let mut string = String::new();
+        for c in chars.into_iter().filter(|c| *c != '\0') {
+            if string.len() + c.len_utf8() > max_bytes {
+                break;
+            }
+            string.push(c);
+        }
+        string
+    })
+}
+
+/// The longest message an `Error` or `Reject` can carry, as the body of a
+/// package holds at most 255 bytes, the terminator included.
+pub const MAX_MESSAGE_LENGTH: usize = 254;
+
+/// Implement `Arbitrary` for each type by mapping the given strategy.
+#[cfg(any(feature = "server", feature = "centralex", feature = "client"))]
+macro_rules! arbitrary {
+    ($($ty:ty => $strategy:expr;)*) => {
+        $(
+            impl Arbitrary for $ty {
+                type Parameters = ();
+                type Strategy = BoxedStrategy<Self>;
+
+                fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+                    $strategy.boxed()
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(any(feature = "server", feature = "client"))]
+arbitrary! {
+    Extension => any::<u8>().prop_map(Extension::from_wire);
+}
+
+#[cfg(feature = "server")]
+arbitrary! {
+    ClientType => prop_oneof![
+        Just(ClientType::Deleted),
+        Just(ClientType::BaudotHostname),
+        Just(ClientType::BaudotIpaddress),
+        Just(ClientType::AsciiHostname),
+        Just(ClientType::AsciiIpaddress),
+        Just(ClientType::BaudotDynIp),
+        Just(ClientType::Email),
+    ];
+    // 39 bytes and the terminator fill the field
+    String40Bytes => string(39).prop_map(String40Bytes);
+    PeerFlags => any::<u16>().prop_map(PeerFlags::from_bits);
+    DirectoryTimestamp => any::<u32>().prop_map(DirectoryTimestamp::from_raw);
+
+    server::ClientUpdate => (any::<u32>(), any::<u16>(), any::<u16>())
+        .prop_map(|(number, pin, port)| server::ClientUpdate { number, pin, port });
+    server::AddressConfirm => any::<[u8; 4]>().prop_map(|ipaddress| server::AddressConfirm {
+        ipaddress: ipaddress.into(),
+    });
+    server::PeerQuery => (any::<u32>(), any::<u8>())
+        .prop_map(|(number, version)| server::PeerQuery { number, version });
+    server::PeerNotFound => Just(server::PeerNotFound {});
+    server::PeerReply => (
+        (any::<u32>(), any::<String40Bytes>(), any::<PeerFlags>(), any::<ClientType>()),
+        (any::<String40Bytes>(), any::<[u8; 4]>(), any::<u16>()),
+        (any::<Extension>(), any::<u16>(), any::<DirectoryTimestamp>()),
+    )
+        .prop_map(
+            |(
+                (number, name, flags, client_type),
+                (hostname, ipaddress, port),
+                (extension, pin, timestamp),
+            )| server::PeerReply {
+                number,
+                name,
+                flags,
+                client_type,
+                hostname,
+                ipaddress: ipaddress.into(),
+                port,
+                extension,
+                pin,
+                timestamp,
+            },
+        );
+    server::FullQuery => (any::<u8>(), any::<u32>())
+        .prop_map(|(version, server_pin)| server::FullQuery { version, server_pin });
+    server::Login => (any::<u8>(), any::<u32>())
+        .prop_map(|(version, server_pin)| server::Login { version, server_pin });
+    server::Acknowledge => Just(server::Acknowledge {});
+    server::EndOfList => Just(server::EndOfList {});
+    server::PeerSearch => (any::<u8>(), any::<String40Bytes>())
+        .prop_map(|(version, pattern)| server::PeerSearch { version, pattern });
+    server::Error => string(MAX_MESSAGE_LENGTH).prop_map(|message| server::Error { message });
+
+    Package<Server> => prop_oneof![
+        any::<server::ClientUpdate>().prop_map(Package::new),
+        any::<server::AddressConfirm>().prop_map(Package::new),
+        any::<server::PeerQuery>().prop_map(Package::new),
+        any::<server::PeerNotFound>().prop_map(Package::new),
+        any::<server::PeerReply>().prop_map(Package::new),
+        any::<server::FullQuery>().prop_map(Package::new),
+        any::<server::Login>().prop_map(Package::new),
+        any::<server::Acknowledge>().prop_map(Package::new),
+        any::<server::EndOfList>().prop_map(Package::new),
+        any::<server::PeerSearch>().prop_map(Package::new),
+        any::<server::Error>().prop_map(Package::new),
+    ];
+}
+
+#[cfg(feature = "centralex")]
+arbitrary! {
+    centralex::Heartbeat => Just(centralex::Heartbeat {});
+    centralex::End => Just(centralex::End {});
+    centralex::Reject => string(MAX_MESSAGE_LENGTH).prop_map(centralex::Reject::from);
+    centralex::RemConnect => (any::<u32>(), any::<u16>())
+        .prop_map(|(number, pin)| centralex::RemConnect { number, pin });
+    centralex::RemConfirm => Just(centralex::RemConfirm {});
+    centralex::RemCall => (any::<[u8; 4]>(), any::<[u8; 16]>()).prop_map(
+        |(remote_ip_v4, remote_ip_v6)| centralex::RemCall {
+            remote_ip_v4: remote_ip_v4.into(),
+            remote_ip_v6: remote_ip_v6.into(),
+        }
+    );
+    centralex::RemAck => Just(centralex::RemAck {});
+
+    Package<Centralex> => prop_oneof![
+        any::<centralex::Heartbeat>().prop_map(Package::new),
+        any::<centralex::End>().prop_map(Package::new),
+        any::<centralex::Reject>().prop_map(Package::new),
+        any::<centralex::RemConnect>().prop_map(Package::new),
+        any::<centralex::RemConfirm>().prop_map(Package::new),
+        any::<centralex::RemCall>().prop_map(Package::new),
+        any::<centralex::RemAck>().prop_map(Package::new),
+    ];
+}
+
+#[cfg(feature = "client")]
+arbitrary! {
+    client::Heartbeat => Just(client::Heartbeat {});
+    client::DirectDial => any::<Extension>().prop_map(|extension| client::DirectDial { extension });
+    client::End => Just(client::End {});
+    client::Reject => string(MAX_MESSAGE_LENGTH).prop_map(|message| client::Reject { message });
+
+    Package<Client> => prop_oneof![
+        any::<client::Heartbeat>().prop_map(Package::new),
+        any::<client::DirectDial>().prop_map(Package::new),
+        any::<client::End>().prop_map(Package::new),
+        any::<client::Reject>().prop_map(Package::new),
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn strings_fit(string in string(39)) {
+            prop_assert!(string.len() <= 39);
+            prop_assert!(!string.contains('\0'));
+        }
+    }
+
+    /// Assert that `package` comes out of a round trip over the wire unchanged.
+    #[cfg(any(feature = "server", feature = "centralex", feature = "client"))]
+    macro_rules! round_trip {
+        ($class:ty, $package:expr) => {{
+            let package = $package;
+            let mut bytes = Vec::new();
+            package.serialize(&mut bytes).unwrap();
+            prop_assert_eq!(bytes[1] as usize, bytes.len() - 2);
+
+            let decoded = Package::<$class>::deserialize(&mut &bytes[..]).unwrap();
+            prop_assert_eq!(decoded, package);
+        }};
+    }
+
+    #[cfg(feature = "server")]
+    proptest! {
+        #[test]
+        fn server_round_trip(package in any::<Package<Server>>()) {
+            round_trip!(Server, package);
+        }
+    }
+
+    #[cfg(feature = "centralex")]
+    proptest! {
+        #[test]
+        fn centralex_round_trip(package in any::<Package<Centralex>>()) {
+            round_trip!(Centralex, package);
+        }
+    }
+
+    #[cfg(feature = "client")]
+    proptest! {
+        #[test]
+        fn client_round_trip(package in any::<Package<Client>>()) {
+            round_trip!(Client, package);
+        }
+    }
+}
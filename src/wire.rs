@@ -0,0 +1,91 @@
+//! Encoding of the fields of package bodies.
+//!
+//! Numbers are little endian, addresses are sent as their octets. Structs
+//! are encoded as their fields in order, which `wire_struct!` implements.
+
+use crate::io::{self, Read, Write};
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+/// Types which can be written as part of a package body.
+pub trait Encode {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()>;
+}
+
+/// Types which can be read as part of a package body.
+pub trait Decode: Sized {
+    fn decode(reader: &mut impl Read) -> io::Result<Self>;
+}
+
+macro_rules! numbers {
+    ($($number:ty),*) => {
+        $(
+            impl Encode for $number {
+                fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+                    writer.write_all(&self.to_le_bytes())
+                }
+            }
+
+            impl Decode for $number {
+                fn decode(reader: &mut impl Read) -> io::Result<Self> {
+                    let mut bytes = [0; core::mem::size_of::<$number>()];
+                    reader.read_exact(&mut bytes)?;
+                    Ok(<$number>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+numbers!(u8, u16, u32, u64);
+
+impl Encode for Ipv4Addr {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.octets())
+    }
+}
+
+impl Decode for Ipv4Addr {
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        let mut octets = [0; 4];
+        reader.read_exact(&mut octets)?;
+        Ok(octets.into())
+    }
+}
+
+impl Encode for Ipv6Addr {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.octets())
+    }
+}
+
+impl Decode for Ipv6Addr {
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        let mut octets = [0; 16];
+        reader.read_exact(&mut octets)?;
+        Ok(octets.into())
+    }
+}
+
+/// Implement `Encode` and `Decode` for structs by encoding the listed fields
+/// in order.
+#[cfg(any(feature = "server", feature = "client", feature = "centralex"))]
+macro_rules! wire_struct {
+    ($($name:ident { $($field:ident),* $(,)? })*) => {
+        $(
+            impl $crate::wire::Encode for $name {
+                fn encode(&self, _writer: &mut impl $crate::io::Write) -> $crate::io::Result<()> {
+                    $($crate::wire::Encode::encode(&self.$field, _writer)?;)*
+                    Ok(())
+                }
+            }
+
+            impl $crate::wire::Decode for $name {
+                fn decode(_reader: &mut impl $crate::io::Read) -> $crate::io::Result<Self> {
+                    Ok($name {
+                        $($field: $crate::wire::Decode::decode(_reader)?,)*
+                    })
+                }
+            }
+        )*
+    };
+}